@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+//An actor is a thread that owns some state and only ever touches it while handling messages from
+// its mailbox, one message at a time. Nothing else can reach the state, so it needs no locking of
+// its own. The only shared thing is the mailbox.
+pub struct Actor<M> {
+    mailbox: Arc<Mailbox<M>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+//What send() does when a bounded mailbox is already full.
+#[derive(Clone, Copy, Debug)]
+pub enum OverflowPolicy {
+    //Wait for the actor to make room, which slows the sender down to the actor's pace.
+    Block,
+    //Throw away the message being sent.
+    DropNewest,
+    //Throw away the message that has been waiting longest, so the actor always works on the most
+    // recent messages. Good for things like status updates where only the latest one matters.
+    DropOldest,
+}
+
+pub struct ActorBuilder {
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+}
+
+struct Mailbox<M> {
+    state: Mutex<MailboxState<M>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    dropped: AtomicUsize,
+}
+
+struct MailboxState<M> {
+    queue: VecDeque<M>,
+    closed: bool,
+}
+
+impl Default for ActorBuilder {
+    fn default() -> Self {
+        ActorBuilder::new()
+    }
+}
+
+impl ActorBuilder {
+    //Without a call to mailbox() the mailbox is unbounded and send() never waits.
+    pub fn new() -> ActorBuilder {
+        ActorBuilder {
+            capacity: None,
+            policy: OverflowPolicy::Block,
+        }
+    }
+
+    pub fn mailbox(mut self, capacity: usize, policy: OverflowPolicy) -> ActorBuilder {
+        assert!(capacity > 0, "A mailbox needs room for at least one message");
+        self.capacity = Some(capacity);
+        self.policy = policy;
+        self
+    }
+
+    pub fn spawn<M, F>(self, mut handler: F) -> Actor<M>
+    where
+        M: Send + 'static,
+        F: FnMut(M) + Send + 'static,
+    {
+        let mailbox = Arc::new(Mailbox {
+            state: Mutex::new(MailboxState {
+                queue: VecDeque::new(),
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: self.capacity,
+            policy: self.policy,
+            dropped: AtomicUsize::new(0),
+        });
+
+        let thread = {
+            let mailbox = Arc::clone(&mailbox);
+            thread::spawn(move || {
+                while let Some(message) = mailbox.take() {
+                    handler(message);
+                }
+            })
+        };
+
+        Actor {
+            mailbox,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<M> Mailbox<M> {
+    //Returns None once the mailbox is closed and everything in it has been handled.
+    fn take(&self) -> Option<M> {
+        let state = self.state.lock().unwrap();
+        let mut state = self
+            .not_empty
+            .wait_while(state, |state| state.queue.is_empty() && !state.closed)
+            .unwrap();
+
+        let message = state.queue.pop_front();
+        self.not_full.notify_one();
+        message
+    }
+
+    fn is_full(&self, state: &MailboxState<M>) -> bool {
+        self.capacity.is_some_and(|capacity| state.queue.len() >= capacity)
+    }
+
+    fn put(&self, message: M) {
+        let mut state = self.state.lock().unwrap();
+
+        if self.is_full(&state) {
+            match self.policy {
+                OverflowPolicy::Block => {
+                    state = self
+                        .not_full
+                        .wait_while(state, |state| self.is_full(state))
+                        .unwrap();
+                }
+                OverflowPolicy::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::SeqCst);
+                    return;
+                }
+                OverflowPolicy::DropOldest => {
+                    state.queue.pop_front();
+                    self.dropped.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+
+        state.queue.push_back(message);
+        self.not_empty.notify_one();
+    }
+}
+
+impl<M> Actor<M> {
+    pub fn send(&self, message: M) {
+        self.mailbox.put(message);
+    }
+
+    //How many messages the overflow policy has thrown away so far.
+    pub fn dropped(&self) -> usize {
+        self.mailbox.dropped.load(Ordering::SeqCst)
+    }
+}
+
+//The actor handles whatever is still in its mailbox before its thread exits, dropping the handle
+// waits for that.
+impl<M> Drop for Actor<M> {
+    fn drop(&mut self) {
+        self.mailbox.state.lock().unwrap().closed = true;
+        self.mailbox.not_empty.notify_all();
+
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("Actor thread crashed");
+        }
+    }
+}
+
+//Sends 0 to 20 to an actor with room for 3 messages, while the actor is stuck on message 0.
+// Returns what the actor ended up handling and how many messages were dropped.
+fn flood_actor(policy: OverflowPolicy) -> (Vec<u32>, usize) {
+    let processed = Arc::new(Mutex::new(Vec::new()));
+    let (started_tx, started_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+
+    let actor = {
+        let processed = Arc::clone(&processed);
+        ActorBuilder::new()
+            .mailbox(3, policy)
+            .spawn(move |message: u32| {
+                if message == 0 {
+                    started_tx.send(()).expect("Main thread hung up");
+                    let _ = release_rx.recv();
+                }
+                processed.lock().unwrap().push(message);
+            })
+    };
+
+    actor.send(0);
+    started_rx.recv().expect("Actor never started");
+
+    //With Block the flood can't finish until the actor gets going again, so something else has to
+    // let it go. The other policies never wait, so there the actor is only let go after the flood.
+    let releaser = matches!(policy, OverflowPolicy::Block).then(|| {
+        let release_tx = release_tx.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            let _ = release_tx.send(());
+        })
+    });
+
+    for message in 1..=20 {
+        actor.send(message);
+    }
+    let _ = release_tx.send(());
+    if let Some(releaser) = releaser {
+        releaser.join().expect("Releaser crashed");
+    }
+
+    let dropped = actor.dropped();
+    drop(actor);
+
+    let processed = processed.lock().unwrap().clone();
+    (processed, dropped)
+}
+
+pub fn flooding_a_bounded_mailbox() {
+    for policy in [OverflowPolicy::DropOldest, OverflowPolicy::DropNewest, OverflowPolicy::Block] {
+        let (processed, dropped) = flood_actor(policy);
+        println!("{:?} actor processed {:?} and dropped {dropped}", policy, processed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_policy_keeps_and_drops_the_right_messages() {
+        assert_eq!(flood_actor(OverflowPolicy::Block), ((0..=20).collect(), 0));
+        assert_eq!(flood_actor(OverflowPolicy::DropNewest), (vec![0, 1, 2, 3], 17));
+        assert_eq!(flood_actor(OverflowPolicy::DropOldest), (vec![0, 18, 19, 20], 17));
+    }
+
+    #[test]
+    fn block_policy_holds_the_sender_until_there_is_room() {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let actor = Arc::new(ActorBuilder::new().mailbox(2, OverflowPolicy::Block).spawn(
+            move |message: u32| {
+                if message == 0 {
+                    started_tx.send(()).unwrap();
+                    release_rx.recv().unwrap();
+                }
+            },
+        ));
+
+        actor.send(0);
+        started_rx.recv().unwrap();
+        actor.send(1);
+        actor.send(2);
+
+        let sender = {
+            let actor = Arc::clone(&actor);
+            thread::spawn(move || actor.send(3))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!sender.is_finished(), "send() went through on a full mailbox");
+
+        release_tx.send(()).unwrap();
+        sender.join().unwrap();
+        assert_eq!(actor.dropped(), 0);
+    }
+}
@@ -0,0 +1,201 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::concurrent_collections::BlockingStack;
+
+//The queue-like types in this crate that can be benchmarked against each other. There is no
+// separate MPMC channel or lock-free queue here yet, so those can't be compared. std's mpsc only
+// has one receiver, so for several consumers the receiver is shared behind a Mutex<T>, the same
+// way the ThreadPool shares it between workers.
+#[derive(Debug, Clone, Copy)]
+pub enum ChannelImpl {
+    Mpsc,
+    SyncChannel(usize),
+    BlockingStack(usize),
+}
+
+#[derive(Debug)]
+pub struct BenchResult {
+    pub messages: usize,
+    pub elapsed: Duration,
+    pub messages_per_sec: f64,
+}
+
+type SendFn = Box<dyn Fn(usize) + Send + Sync>;
+type RecvFn = Box<dyn Fn() -> usize + Send + Sync>;
+
+//Every implementation is wrapped up as a pair of closures so the benchmark loop doesn't need to
+// know which one it is running.
+fn endpoints(channel: ChannelImpl) -> (SendFn, RecvFn) {
+    match channel {
+        ChannelImpl::Mpsc => {
+            let (tx, rx) = mpsc::channel();
+            let rx = Mutex::new(rx);
+            (
+                Box::new(move |value| tx.send(value).expect("Consumers hung up")),
+                Box::new(move || rx.lock().unwrap().recv().expect("Producers hung up")),
+            )
+        }
+        ChannelImpl::SyncChannel(capacity) => {
+            let (tx, rx) = mpsc::sync_channel(capacity);
+            let rx = Mutex::new(rx);
+            (
+                Box::new(move |value| tx.send(value).expect("Consumers hung up")),
+                Box::new(move || rx.lock().unwrap().recv().expect("Producers hung up")),
+            )
+        }
+        ChannelImpl::BlockingStack(capacity) => {
+            let stack = Arc::new(BlockingStack::new(capacity));
+            let pop_stack = Arc::clone(&stack);
+            (
+                Box::new(move |value| stack.push(value)),
+                Box::new(move || pop_stack.pop()),
+            )
+        }
+    }
+}
+
+//Sends `messages` values split across the producers and times how long it takes the consumers
+// to receive all of them. Consumers claim a message before receiving it, which is how they know
+// when to stop without every implementation needing a way to close it.
+pub fn bench_channel(
+    channel: ChannelImpl,
+    producers: usize,
+    consumers: usize,
+    messages: usize,
+) -> BenchResult {
+    assert!(producers > 0 && consumers > 0, "Need at least one producer and one consumer");
+
+    let (send, recv) = endpoints(channel);
+    let claimed = AtomicUsize::new(0);
+    let received_sum = AtomicUsize::new(0);
+
+    let started = Instant::now();
+    thread::scope(|s| {
+        for producer in 0..producers {
+            let send = &send;
+            s.spawn(move || {
+                for value in (producer..messages).step_by(producers) {
+                    send(value);
+                }
+            });
+        }
+
+        for _ in 0..consumers {
+            s.spawn(|| {
+                while claimed.fetch_add(1, Ordering::Relaxed) < messages {
+                    received_sum.fetch_add(recv(), Ordering::Relaxed);
+                }
+            });
+        }
+    });
+    let elapsed = started.elapsed();
+
+    assert_eq!(
+        received_sum.into_inner(),
+        (0..messages).sum::<usize>(),
+        "Every message should be received exactly once"
+    );
+
+    BenchResult {
+        messages,
+        elapsed,
+        messages_per_sec: messages as f64 / elapsed.as_secs_f64(),
+    }
+}
+
+pub fn benchmarking_channels() {
+    for channel in [
+        ChannelImpl::Mpsc,
+        ChannelImpl::SyncChannel(64),
+        ChannelImpl::BlockingStack(64),
+    ] {
+        let result = bench_channel(channel, 2, 2, 20_000);
+        println!(
+            "{:?}: {} messages in {:?}, {:.0} messages/sec",
+            channel, result.messages, result.elapsed, result.messages_per_sec
+        );
+    }
+}
+
+#[derive(Debug)]
+pub struct OrderingResult {
+    pub ordering: Ordering,
+    pub total: u64,
+    pub elapsed: Duration,
+}
+
+//Times `threads` threads each doing `iters` fetch_add()s on one shared counter, once per
+// ordering. The final count comes back along with the time, because ordering only decides what
+// other memory operations can be reordered around the atomic one, never whether the increment
+// itself is atomic. Even Relaxed never loses an increment. How much the timings differ depends a
+// lot on the CPU. On x86 every read-modify-write is a locked instruction anyway, so the three
+// usually come out about the same, on ARM the stronger orderings need extra barriers.
+pub fn bench_orderings(threads: usize, iters: usize) -> Vec<OrderingResult> {
+    [Ordering::Relaxed, Ordering::SeqCst, Ordering::AcqRel]
+        .into_iter()
+        .map(|ordering| {
+            let counter = AtomicU64::new(0);
+            let started = Instant::now();
+
+            thread::scope(|s| {
+                for _ in 0..threads {
+                    s.spawn(|| {
+                        for _ in 0..iters {
+                            counter.fetch_add(1, ordering);
+                        }
+                    });
+                }
+            });
+
+            OrderingResult {
+                ordering,
+                elapsed: started.elapsed(),
+                total: counter.into_inner(),
+            }
+        })
+        .collect()
+}
+
+pub fn benchmarking_atomic_orderings() {
+    for result in bench_orderings(4, 250_000) {
+        println!(
+            "1,000,000 fetch_add()s with {:?}: {:?}, final count {}",
+            result.ordering, result.elapsed, result.total
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_channel_reports_every_message() {
+        for channel in [
+            ChannelImpl::Mpsc,
+            ChannelImpl::SyncChannel(0),
+            ChannelImpl::SyncChannel(8),
+            ChannelImpl::BlockingStack(8),
+        ] {
+            for (producers, consumers) in [(1, 1), (3, 2)] {
+                let result = bench_channel(channel, producers, consumers, 5_000);
+                assert_eq!(result.messages, 5_000, "{channel:?}");
+                assert!(result.elapsed > Duration::ZERO, "{channel:?}");
+                assert!(result.messages_per_sec > 0.0, "{channel:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn no_ordering_loses_an_increment() {
+        let results = bench_orderings(4, 20_000);
+        let orderings: Vec<Ordering> = results.iter().map(|result| result.ordering).collect();
+        assert_eq!(orderings, [Ordering::Relaxed, Ordering::SeqCst, Ordering::AcqRel]);
+        for result in results {
+            assert_eq!(result.total, 80_000, "{:?} lost increments", result.ordering);
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, mpsc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//mpsc channels hand each message to exactly one receiver. A broadcast channel hands a clone of
+// every message to every subscriber instead. Each subscriber has its own bounded inbox so one slow
+// subscriber can't make the sender wait. When an inbox is full the oldest message in it is thrown
+// away and the subscriber is told how many it missed the next time it calls recv().
+pub struct Broadcaster<T: Clone> {
+    capacity: usize,
+    subscribers: Mutex<Vec<Weak<Inbox<T>>>>,
+}
+
+struct Inbox<T> {
+    state: Mutex<InboxState<T>>,
+    ready: Condvar,
+}
+
+struct InboxState<T> {
+    queue: VecDeque<T>,
+    missed: usize,
+    closed: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BroadcastRecvError {
+    //The subscriber fell behind and this many messages were dropped from its inbox.
+    Lagged(usize),
+    //The broadcaster is gone and every message has already been received.
+    Closed,
+}
+
+impl<T: Clone> Broadcaster<T> {
+    pub fn new(capacity: usize) -> Broadcaster<T> {
+        assert!(capacity > 0, "Subscribers need room for at least one message");
+
+        Broadcaster {
+            capacity,
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    //Subscribers only see messages sent after they subscribed.
+    pub fn subscribe(&self) -> Subscriber<T> {
+        let inbox = Arc::new(Inbox {
+            state: Mutex::new(InboxState {
+                queue: VecDeque::with_capacity(self.capacity),
+                missed: 0,
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        });
+
+        //The broadcaster only keeps a Weak<T> to each inbox so that a dropped subscriber doesn't
+        // keep collecting messages nobody will read.
+        self.subscribers.lock().unwrap().push(Arc::downgrade(&inbox));
+
+        Subscriber { inbox }
+    }
+
+    //Returns how many subscribers the message was delivered to.
+    pub fn send(&self, value: T) -> usize {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| subscriber.strong_count() > 0);
+
+        for inbox in subscribers.iter().filter_map(Weak::upgrade) {
+            let mut state = inbox.state.lock().unwrap();
+            if state.queue.len() == self.capacity {
+                state.queue.pop_front();
+                state.missed += 1;
+            }
+            state.queue.push_back(value.clone());
+            inbox.ready.notify_one();
+        }
+
+        subscribers.len()
+    }
+}
+
+impl<T: Clone> Drop for Broadcaster<T> {
+    fn drop(&mut self) {
+        for inbox in self.subscribers.lock().unwrap().iter().filter_map(Weak::upgrade) {
+            inbox.state.lock().unwrap().closed = true;
+            inbox.ready.notify_all();
+        }
+    }
+}
+
+pub struct Subscriber<T> {
+    inbox: Arc<Inbox<T>>,
+}
+
+impl<T> Subscriber<T> {
+    //A lag is reported once, before any of the messages that survived, so the consumer finds out
+    // about the gap at the point where it happened.
+    pub fn recv(&self) -> Result<T, BroadcastRecvError> {
+        let state = self.inbox.state.lock().unwrap();
+        let mut state = self
+            .inbox
+            .ready
+            .wait_while(state, |state| {
+                state.missed == 0 && state.queue.is_empty() && !state.closed
+            })
+            .unwrap();
+
+        if state.missed > 0 {
+            let missed = state.missed;
+            state.missed = 0;
+            return Err(BroadcastRecvError::Lagged(missed));
+        }
+
+        state.queue.pop_front().ok_or(BroadcastRecvError::Closed)
+    }
+}
+
+pub fn detecting_lagging_subscribers() {
+    let broadcaster = Broadcaster::new(3);
+    let fast = broadcaster.subscribe();
+    let slow = broadcaster.subscribe();
+
+    for i in 0..8 {
+        broadcaster.send(i);
+        //The fast subscriber keeps up by reading every message as it arrives.
+        println!("fast subscriber got {:?}", fast.recv());
+    }
+
+    drop(broadcaster);
+
+    //The slow subscriber only starts reading now. Its inbox holds 3 messages, so 5 were lost.
+    loop {
+        match slow.recv() {
+            Ok(value) => println!("slow subscriber got {value}"),
+            Err(BroadcastRecvError::Lagged(missed)) => {
+                println!("slow subscriber lagged and missed {missed} messages")
+            }
+            Err(BroadcastRecvError::Closed) => {
+                println!("slow subscriber saw the broadcaster close");
+                break;
+            }
+        }
+    }
+}
+
+//One thread keeps time for everybody. Every `interval` it broadcasts the number of the tick, 1,
+// 2, 3 and so on, and every subscriber gets the same numbers at about the same moment, which
+// keeps periodic work on different threads lined up. Ticks are scheduled from the start time
+// instead of sleeping `interval` after each send, so a late tick doesn't push all the later ones
+// back. A subscriber that falls behind sees Lagged like any other broadcast subscriber.
+pub struct Ticker {
+    broadcaster: Arc<Broadcaster<u64>>,
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Ticker {
+    pub fn new(interval: Duration, capacity: usize) -> Ticker {
+        let broadcaster = Arc::new(Broadcaster::new(capacity));
+        let (stop, stop_rx) = mpsc::channel::<()>();
+
+        let thread = {
+            let broadcaster = Arc::clone(&broadcaster);
+            thread::spawn(move || {
+                let start = Instant::now();
+                for tick in 1u64.. {
+                    let due = start + interval * tick as u32;
+                    let wait = due.saturating_duration_since(Instant::now());
+                    //Same interruptible sleep as HeartbeatMonitor, the stop channel only ever
+                    // disconnects.
+                    if let Err(mpsc::RecvTimeoutError::Disconnected) = stop_rx.recv_timeout(wait) {
+                        break;
+                    }
+                    broadcaster.send(tick);
+                }
+            })
+        };
+
+        Ticker {
+            broadcaster,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn subscribe(&self) -> Subscriber<u64> {
+        self.broadcaster.subscribe()
+    }
+}
+
+//Once the ticker thread is gone the last Arc to the broadcaster goes with the Ticker, which
+// closes every subscriber.
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("Ticker thread crashed");
+        }
+    }
+}
+
+pub fn ticking_subscribers_in_sync() {
+    let ticker = Ticker::new(Duration::from_millis(10), 8);
+    let subscribers = [ticker.subscribe(), ticker.subscribe()];
+
+    let ticks: Vec<Vec<u64>> = thread::scope(|s| {
+        let handles: Vec<_> = subscribers
+            .iter()
+            .map(|subscriber| {
+                s.spawn(move || {
+                    (0..5)
+                        .map(|_| subscriber.recv().expect("Ticker stopped early"))
+                        .collect::<Vec<u64>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Tick worker crashed"))
+            .collect()
+    });
+
+    println!("ticks seen by each worker: {:?}, in sync: {}", ticks, ticks[0] == ticks[1]);
+
+    drop(ticker);
+    println!("subscriber after the ticker stopped: {:?}", subscribers[0].recv());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_subscriber_is_told_how_many_it_missed() {
+        let broadcaster = Broadcaster::new(3);
+        let fast = broadcaster.subscribe();
+        let slow = broadcaster.subscribe();
+
+        for i in 0..8 {
+            assert_eq!(broadcaster.send(i), 2);
+            assert_eq!(fast.recv(), Ok(i));
+        }
+        drop(broadcaster);
+
+        //The inbox holds the last 3 messages, the 5 before them were dropped.
+        assert_eq!(slow.recv(), Err(BroadcastRecvError::Lagged(5)));
+        assert_eq!(slow.recv(), Ok(5));
+        assert_eq!(slow.recv(), Ok(6));
+        assert_eq!(slow.recv(), Ok(7));
+        assert_eq!(slow.recv(), Err(BroadcastRecvError::Closed));
+        assert_eq!(fast.recv(), Err(BroadcastRecvError::Closed));
+    }
+
+    #[test]
+    fn every_subscriber_sees_the_same_ticks() {
+        //Subscribing takes microseconds and the first tick is 50ms away, so nobody misses it.
+        let ticker = Ticker::new(Duration::from_millis(50), 64);
+        let subscribers = [ticker.subscribe(), ticker.subscribe(), ticker.subscribe()];
+
+        let ticks: Vec<Vec<u64>> = thread::scope(|s| {
+            let handles: Vec<_> = subscribers
+                .iter()
+                .map(|subscriber| {
+                    s.spawn(move || (0..4).map(|_| subscriber.recv().unwrap()).collect::<Vec<_>>())
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        for seen in &ticks {
+            assert_eq!(*seen, vec![1, 2, 3, 4]);
+        }
+
+        //Dropping the ticker closes every subscriber once it has read what was left.
+        drop(ticker);
+        for subscriber in &subscribers {
+            while let Ok(tick) = subscriber.recv() {
+                assert!(tick > 4);
+            }
+            assert_eq!(subscriber.recv(), Err(BroadcastRecvError::Closed));
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+//These adapters work like iterator adapters, but for channels. Each one spawns a forwarding
+// thread that reads from the input receiver and sends onto a new channel, and returns the new
+// receiver. The forwarding thread exits when the input disconnects, which drops its sender, so
+// the output disconnects right after the input does. It also exits if the output receiver is
+// dropped, because there is no point in reading input that nobody will see.
+
+pub fn map_receiver<T, U, F>(rx: Receiver<T>, f: F) -> Receiver<U>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    F: Fn(T) -> U + Send + 'static,
+{
+    let (tx, mapped) = mpsc::channel();
+
+    thread::spawn(move || {
+        for value in rx {
+            if tx.send(f(value)).is_err() {
+                break;
+            }
+        }
+    });
+
+    mapped
+}
+
+pub fn filter_receiver<T, P>(rx: Receiver<T>, pred: P) -> Receiver<T>
+where
+    T: Send + 'static,
+    P: Fn(&T) -> bool + Send + 'static,
+{
+    let (tx, filtered) = mpsc::channel();
+
+    thread::spawn(move || {
+        for value in rx.into_iter().filter(|value| pred(value)) {
+            if tx.send(value).is_err() {
+                break;
+            }
+        }
+    });
+
+    filtered
+}
+
+//Deciding whether an item is a repeat means comparing it to the last item that went out, but
+// send() moves the item away, so the forwarding thread keeps a clone of it. Every new item is
+// forwarded as soon as it arrives, which matters for a stream of events where the latest one
+// shouldn't sit around waiting for something different to show up.
+pub fn dedup_receiver<T>(rx: Receiver<T>) -> Receiver<T>
+where
+    T: PartialEq + Clone + Send + 'static,
+{
+    let (tx, deduped) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut last: Option<T> = None;
+        for value in rx {
+            if last.as_ref() == Some(&value) {
+                continue;
+            }
+            last = Some(value.clone());
+            if tx.send(value).is_err() {
+                break;
+            }
+        }
+    });
+
+    deduped
+}
+
+//Groups the input into batches of `size`. Whatever is left over when the input disconnects goes
+// out as a final short batch instead of being lost.
+pub fn window_receiver<T>(rx: Receiver<T>, size: usize) -> Receiver<Vec<T>>
+where
+    T: Send + 'static,
+{
+    assert!(size > 0, "Windows need to hold at least one item");
+
+    let (tx, windows) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut window = Vec::with_capacity(size);
+        for value in rx {
+            window.push(value);
+            if window.len() == size {
+                let full = std::mem::replace(&mut window, Vec::with_capacity(size));
+                if tx.send(full).is_err() {
+                    return;
+                }
+            }
+        }
+
+        if !window.is_empty() {
+            let _ = tx.send(window);
+        }
+    });
+
+    windows
+}
+
+//Merges receivers that each deliver their items in sorted order into one sorted receiver. The
+// forwarding thread peeks one item from every input and keeps those in a min-heap (BinaryHeap is
+// a max-heap, Reverse flips it), tagged with which input they came from. The smallest one is
+// forwarded and replaced by the next item from the same input. An input that disconnects just
+// stops contributing items.
+pub fn merge_sorted<T>(receivers: Vec<Receiver<T>>) -> Receiver<T>
+where
+    T: Ord + Send + 'static,
+{
+    let (tx, merged) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut fronts = BinaryHeap::new();
+        for (input, rx) in receivers.iter().enumerate() {
+            if let Ok(value) = rx.recv() {
+                fronts.push(Reverse((value, input)));
+            }
+        }
+
+        while let Some(Reverse((value, input))) = fronts.pop() {
+            if tx.send(value).is_err() {
+                return;
+            }
+            if let Ok(next) = receivers[input].recv() {
+                fronts.push(Reverse((next, input)));
+            }
+        }
+    });
+
+    merged
+}
+
+pub fn transforming_receivers() {
+    let (tx, rx) = mpsc::channel();
+    for i in 0..5 {
+        tx.send(i).expect("Receiver is still alive");
+    }
+    drop(tx);
+
+    let doubled: Vec<i32> = map_receiver(rx, |x| x * 2).into_iter().collect();
+    println!("mapped receiver: {:?}", doubled);
+
+    let (tx, rx) = mpsc::channel();
+    let evens = filter_receiver(rx, |x| x % 2 == 0);
+    for i in 0..10 {
+        tx.send(i).expect("Receiver is still alive");
+    }
+    drop(tx);
+
+    //This loop only ends because dropping tx closed the filtered receiver too.
+    let evens: Vec<i32> = evens.into_iter().collect();
+    println!("filtered receiver: {:?}", evens);
+
+    let (tx, rx) = mpsc::channel();
+    for value in [1, 1, 2, 2, 2, 3, 1] {
+        tx.send(value).expect("Receiver is still alive");
+    }
+    drop(tx);
+
+    let deduped: Vec<i32> = dedup_receiver(rx).into_iter().collect();
+    println!("deduped receiver: {:?}", deduped);
+
+    let (tx, rx) = mpsc::channel();
+    for value in 0..7 {
+        tx.send(value).expect("Receiver is still alive");
+    }
+    drop(tx);
+
+    let windows: Vec<Vec<i32>> = window_receiver(rx, 3).into_iter().collect();
+    println!("windowed receiver: {:?}", windows);
+
+    let sorted_streams: Vec<Receiver<i32>> = [[1, 3, 5], [2, 4, 6]]
+        .into_iter()
+        .map(|values| {
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                for value in values {
+                    tx.send(value).expect("Receiver is still alive");
+                }
+            });
+            rx
+        })
+        .collect();
+
+    let merged: Vec<i32> = merge_sorted(sorted_streams).into_iter().collect();
+    println!("merged sorted receivers: {:?}", merged);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn map_receiver_maps_and_disconnects_with_its_input() {
+        let (tx, rx) = mpsc::channel();
+        let mapped = map_receiver(rx, |n: i32| n * 10);
+
+        for n in 0..5 {
+            tx.send(n).unwrap();
+        }
+        assert_eq!(mapped.recv(), Ok(0));
+
+        drop(tx);
+        let rest: Vec<i32> = mapped.iter().collect();
+        assert_eq!(rest, vec![10, 20, 30, 40]);
+        assert!(mapped.recv().is_err());
+    }
+
+    #[test]
+    fn filter_receiver_keeps_only_matches_and_disconnects() {
+        let (tx, rx) = mpsc::channel();
+        let evens = filter_receiver(rx, |n: &i32| n % 2 == 0);
+
+        for n in 0..10 {
+            tx.send(n).unwrap();
+        }
+        drop(tx);
+
+        assert_eq!(evens.iter().collect::<Vec<_>>(), vec![0, 2, 4, 6, 8]);
+        assert!(evens.recv().is_err());
+    }
+
+    #[test]
+    fn dedup_receiver_drops_consecutive_repeats() {
+        let (tx, rx) = mpsc::channel();
+        let deduped = dedup_receiver(rx);
+
+        for n in [1, 1, 2, 2, 2, 3, 1] {
+            tx.send(n).unwrap();
+        }
+        drop(tx);
+
+        assert_eq!(deduped.iter().collect::<Vec<_>>(), vec![1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn dedup_receiver_forwards_without_waiting_for_the_next_item() {
+        let (tx, rx) = mpsc::channel();
+        let deduped = dedup_receiver(rx);
+
+        //The input stays connected, so the item can only arrive if it's forwarded right away.
+        tx.send("event").unwrap();
+        assert_eq!(deduped.recv_timeout(Duration::from_secs(5)), Ok("event"));
+        tx.send("event").unwrap();
+        tx.send("other").unwrap();
+        assert_eq!(deduped.recv_timeout(Duration::from_secs(5)), Ok("other"));
+    }
+
+    #[test]
+    fn window_receiver_sends_a_short_final_batch() {
+        let (tx, rx) = mpsc::channel();
+        let windows = window_receiver(rx, 3);
+
+        for n in 0..7 {
+            tx.send(n).unwrap();
+        }
+        drop(tx);
+
+        let windows: Vec<Vec<i32>> = windows.iter().collect();
+        let sizes: Vec<usize> = windows.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![3, 3, 1]);
+        assert_eq!(windows, vec![vec![0, 1, 2], vec![3, 4, 5], vec![6]]);
+    }
+
+    fn streamed(values: Vec<i32>) -> Receiver<i32> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for value in values {
+                tx.send(value).unwrap();
+            }
+        });
+        rx
+    }
+
+    #[test]
+    fn merge_sorted_interleaves_sorted_inputs() {
+        let inputs = vec![vec![1, 3, 5, 5], vec![], vec![0, 2, 4, 6, 8], vec![7]];
+        let mut expected: Vec<i32> = inputs.iter().flatten().copied().collect();
+        expected.sort();
+
+        let merged: Vec<i32> =
+            merge_sorted(inputs.clone().into_iter().map(streamed).collect()).iter().collect();
+        assert_eq!(merged, expected);
+
+        //kway_merge() is built on merge_sorted() and has to give the same answer.
+        assert_eq!(crate::channels::kway_merge(inputs), expected);
+        assert!(merge_sorted(Vec::<Receiver<i32>>::new()).recv().is_err());
+    }
+}
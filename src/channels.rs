@@ -0,0 +1,1099 @@
+use std::cell::OnceCell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::channel_adapters::merge_sorted;
+use crate::parallel::{available_threads, split_into_chunks};
+
+//Messages from different producers can interleave in any order, but messages from the same
+// producer always come out of the channel in the order that producer sent them. Every message is
+// tagged with (producer id, sequence number) so that the collector can check that guarantee while
+// rebuilding each producer's list.
+pub fn collect_preserving_producer_order(producers: Vec<Vec<u32>>) -> HashMap<usize, Vec<u32>> {
+    let (tx, rx) = mpsc::channel();
+
+    for (id, items) in producers.into_iter().enumerate() {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for (seq, item) in items.into_iter().enumerate() {
+                tx.send((id, seq, item)).expect("Collector hung up");
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+    }
+
+    //The original tx has to be dropped here or the loop below would never end.
+    drop(tx);
+
+    let mut collected: HashMap<usize, Vec<u32>> = HashMap::new();
+    for (id, seq, item) in rx {
+        let items = collected.entry(id).or_default();
+        assert_eq!(seq, items.len(), "Producer {id} delivered out of order");
+        items.push(item);
+    }
+
+    collected
+}
+
+pub fn preserving_order_per_producer() {
+    let producers = vec![vec![1, 2, 3, 4], vec![10, 20, 30], vec![100, 200, 300, 400, 500]];
+    let collected = collect_preserving_producer_order(producers.clone());
+
+    for (id, items) in producers.iter().enumerate() {
+        println!(
+            "producer {id}: sent {:?}, received {:?}, in order: {}",
+            items,
+            collected[&id],
+            collected[&id] == *items
+        );
+    }
+}
+
+//Wrapping both halves of a channel so that every message carries the Instant it was sent at. The
+// receiving side works out how long each message sat in the channel and keeps track of it.
+pub fn timed_channel<T>() -> (TimedSender<T>, TimedReceiver<T>) {
+    let (tx, rx) = mpsc::channel();
+
+    (
+        TimedSender { sender: tx },
+        TimedReceiver {
+            receiver: rx,
+            latencies: Vec::new(),
+        },
+    )
+}
+
+pub struct TimedSender<T> {
+    sender: mpsc::Sender<(Instant, T)>,
+}
+
+//Implemented by hand because #[derive(Clone)] would require T: Clone, even though only the
+// Sender is being cloned.
+impl<T> Clone for TimedSender<T> {
+    fn clone(&self) -> Self {
+        TimedSender {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<T> TimedSender<T> {
+    pub fn send(&self, value: T) -> Result<(), mpsc::SendError<T>> {
+        self.sender
+            .send((Instant::now(), value))
+            .map_err(|mpsc::SendError((_, value))| mpsc::SendError(value))
+    }
+}
+
+pub struct TimedReceiver<T> {
+    receiver: mpsc::Receiver<(Instant, T)>,
+    latencies: Vec<Duration>,
+}
+
+#[derive(Debug)]
+pub struct LatencyStats {
+    pub count: usize,
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+}
+
+impl<T> TimedReceiver<T> {
+    pub fn recv(&mut self) -> Result<T, mpsc::RecvError> {
+        let (sent_at, value) = self.receiver.recv()?;
+        self.latencies.push(sent_at.elapsed());
+        Ok(value)
+    }
+
+    //None until at least one message has been received.
+    pub fn stats(&self) -> Option<LatencyStats> {
+        let count = self.latencies.len();
+        let total: Duration = self.latencies.iter().sum();
+
+        Some(LatencyStats {
+            count,
+            min: *self.latencies.iter().min()?,
+            max: *self.latencies.iter().max()?,
+            mean: total / count as u32,
+        })
+    }
+}
+
+pub fn measuring_message_latency() {
+    let (tx, mut rx) = timed_channel();
+
+    let producer = thread::spawn(move || {
+        for i in 0..5 {
+            tx.send(i).expect("Receiver hung up");
+        }
+    });
+    producer.join().expect("Producer crashed");
+
+    //Everything has already been sent, so every message waits at least this long in the channel.
+    let delay = Duration::from_millis(20);
+    thread::sleep(delay);
+
+    while let Ok(value) = rx.recv() {
+        println!("timed receive: {value}");
+    }
+
+    if let Some(stats) = rx.stats() {
+        println!(
+            "latency over {} messages: min {:?}, max {:?}, mean {:?}, all above the {:?} delay: {}",
+            stats.count,
+            stats.min,
+            stats.max,
+            stats.mean,
+            delay,
+            stats.min >= delay
+        );
+    }
+}
+
+//Barrier semantics built out of nothing but channels. Every arriving thread makes a oneshot
+// channel of its own, hands the sending half to a coordinator thread and then blocks on the
+// receiving half. Once the coordinator has collected `n` of them it sends on all of them at once,
+// which releases everybody together. The coordinator keeps going for the next group that shows
+// up, so the barrier can be reused, and it exits once every copy of the closure is dropped.
+pub fn channel_barrier(n: usize) -> impl Fn() + Clone + Send + 'static {
+    assert!(n > 0, "A barrier needs at least one participant");
+
+    let (arrival_tx, arrival_rx) = mpsc::channel::<mpsc::Sender<()>>();
+
+    thread::spawn(move || {
+        let mut waiting = Vec::with_capacity(n);
+        for release in arrival_rx {
+            waiting.push(release);
+            if waiting.len() == n {
+                for release in waiting.drain(..) {
+                    let _ = release.send(());
+                }
+            }
+        }
+    });
+
+    move || {
+        let (release_tx, release_rx) = mpsc::channel();
+        arrival_tx.send(release_tx).expect("Barrier coordinator crashed");
+        release_rx.recv().expect("Barrier coordinator crashed");
+    }
+}
+
+pub fn building_a_barrier_from_channels() {
+    let barrier = channel_barrier(5);
+    let arrived = Arc::new(AtomicUsize::new(0));
+
+    let handles: Vec<_> = (0..5u64)
+        .map(|i| {
+            let barrier = barrier.clone();
+            let arrived = Arc::clone(&arrived);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(i * 10));
+                arrived.fetch_add(1, Ordering::SeqCst);
+                barrier();
+                //Nobody gets here until all five threads have arrived.
+                arrived.load(Ordering::SeqCst)
+            })
+        })
+        .collect();
+
+    let seen: Vec<usize> = handles
+        .into_iter()
+        .map(|handle| handle.join().expect("Barrier thread crashed"))
+        .collect();
+    println!("arrivals seen by each thread after the channel barrier: {:?}", seen);
+}
+
+//The demo in main.rs sends &str values, and since &str is Copy nothing interesting happens to
+// ownership. A Vec<u8> is not Copy, so sending one moves it into the channel and from there to
+// whichever thread receives it. The sender can't touch it afterwards.
+pub fn transfer_ownership_demo() -> usize {
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+
+    let producer = thread::spawn(move || {
+        let buffers = vec![vec![1u8; 3], vec![2u8; 5], vec![3u8; 8]];
+
+        for buffer in buffers {
+            tx.send(buffer).expect("Receiver hung up");
+
+            //The below line is invalid because buffer was moved into the channel by send().
+            // println!("buffer after sending: {:?}", buffer);
+        }
+    });
+
+    let total: usize = rx.iter().map(|buffer| buffer.len()).sum();
+    producer.join().expect("Producer crashed");
+
+    total
+}
+
+pub fn moving_owned_data_through_channels() {
+    println!("total length of the received buffers: {}", transfer_ownership_demo());
+}
+
+//The `for val in rx` loop in main.rs says it probably ends once every transmitter is gone. That
+// is right, the channel counts its senders and recv() only fails once the count gets to zero.
+// Every clone and the original each send one value and then get dropped at the end of their
+// thread, the drops spread out over time. The loop keeps going as long as even one of them is
+// still alive, so it sees every value before it ends.
+pub fn sender_lifecycle_demo() -> usize {
+    const CLONES: u64 = 4;
+    let (tx, rx) = mpsc::channel();
+
+    for i in 0..CLONES {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(5 * i));
+            tx.send(i).expect("Receiver hung up");
+        });
+    }
+
+    //The original sender counts as well, if it weren't moved into a thread and dropped there the
+    // loop below would never end.
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(5 * CLONES));
+        tx.send(CLONES).expect("Receiver hung up");
+    });
+
+    rx.into_iter().count()
+}
+
+pub fn ending_the_loop_when_senders_drop() {
+    println!(
+        "values received before every sender was gone: {} (4 clones plus the original)",
+        sender_lifecycle_demo()
+    );
+}
+
+//Every sorted input gets streamed in through its own producer thread and channel, and
+// merge_sorted() does the actual merging on its own thread.
+pub fn kway_merge(sorted_inputs: Vec<Vec<i32>>) -> Vec<i32> {
+    let receivers: Vec<mpsc::Receiver<i32>> = sorted_inputs
+        .into_iter()
+        .map(|input| {
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                for value in input {
+                    if tx.send(value).is_err() {
+                        break;
+                    }
+                }
+            });
+            rx
+        })
+        .collect();
+
+    merge_sorted(receivers).into_iter().collect()
+}
+
+pub fn merging_sorted_streams() {
+    println!("k-way merge of nothing: {:?}", kway_merge(vec![]));
+    println!("k-way merge of one input: {:?}", kway_merge(vec![vec![1, 4, 9]]));
+    println!(
+        "k-way merge of three inputs: {:?}",
+        kway_merge(vec![vec![1, 4, 7, 10], vec![2, 5, 8], vec![0, 3, 6, 9, 12]])
+    );
+}
+
+//Buckets the values by when they arrived. Each window starts right where the previous one ended,
+// and recv_timeout() is given only the time left in the current window, so a quiet channel still
+// closes its window on time (with a sum of 0 if nothing came in). When the sender disconnects the
+// window in progress is emitted as the last sum.
+pub fn windowed_sum(rx: mpsc::Receiver<i32>, window: Duration) -> Vec<i64> {
+    let mut sums = Vec::new();
+    let mut current = 0i64;
+    let mut window_end = Instant::now() + window;
+
+    loop {
+        let remaining = window_end.saturating_duration_since(Instant::now());
+
+        match rx.recv_timeout(remaining) {
+            Ok(value) => current += i64::from(value),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                sums.push(current);
+                current = 0;
+                window_end += window;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                sums.push(current);
+                return sums;
+            }
+        }
+    }
+}
+
+pub fn summing_values_per_time_window() {
+    let (tx, rx) = mpsc::channel();
+
+    let producer = thread::spawn(move || {
+        for value in [1, 2, 3] {
+            tx.send(value).expect("Aggregator hung up");
+        }
+        thread::sleep(Duration::from_millis(150));
+        for value in [10, 20] {
+            tx.send(value).expect("Aggregator hung up");
+        }
+    });
+
+    println!("windowed sums: {:?}", windowed_sum(rx, Duration::from_millis(100)));
+    producer.join().expect("Producer crashed");
+}
+
+#[derive(Debug)]
+pub struct Timeout;
+
+//Runs `f` on its own thread and waits at most `dur` for the result to come back over a oneshot
+// channel. Rust has no way to kill a thread from the outside, so on a timeout the worker is just
+// left running detached. Whenever it does finish, its send() fails because the receiver is gone,
+// and that error is ignored. If `f` panics the channel disconnects early, which also ends up as
+// a Timeout since no result ever arrived.
+pub fn with_timeout<T, F>(dur: Duration, f: F) -> Result<T, Timeout>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let _ = tx.send(f());
+    });
+
+    rx.recv_timeout(dur).map_err(|_| Timeout)
+}
+
+pub fn timing_out_blocking_work() {
+    let fast = with_timeout(Duration::from_millis(100), || (1..=10).product::<u64>());
+    println!("fast computation with a timeout: {:?}", fast);
+
+    let slow = with_timeout(Duration::from_millis(20), || {
+        thread::sleep(Duration::from_millis(200));
+        "finally done"
+    });
+    println!("slow computation with a timeout: {:?}", slow);
+}
+
+//A channel where the consumer has to say whether each message was handled. Every message travels
+// together with the sending half of its own oneshot channel, and the producer keeps the receiving
+// half as a PendingAck. Like mpsc it comes in two halves, so once every AckSender is gone recv()
+// returns an error instead of waiting for good, and send() fails once the AckReceiver is gone.
+pub fn ack_channel<T>() -> (AckSender<T>, AckReceiver<T>) {
+    let (sender, receiver) = mpsc::channel();
+    (AckSender { sender }, AckReceiver { receiver })
+}
+
+pub struct AckSender<T> {
+    sender: mpsc::Sender<(T, AckHandle)>,
+}
+
+pub struct AckReceiver<T> {
+    receiver: mpsc::Receiver<(T, AckHandle)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AckStatus {
+    Acked,
+    Nacked,
+    //The consumer dropped the AckHandle without answering, most likely because it panicked.
+    Dropped,
+}
+
+pub struct AckHandle {
+    status: mpsc::Sender<AckStatus>,
+}
+
+//Taking self by value means a message can only be answered once.
+impl AckHandle {
+    pub fn ack(self) {
+        let _ = self.status.send(AckStatus::Acked);
+    }
+
+    pub fn nack(self) {
+        let _ = self.status.send(AckStatus::Nacked);
+    }
+}
+
+//The producer's side of a single message, works the same way as JobHandle in the thread pool.
+pub struct PendingAck {
+    receiver: mpsc::Receiver<AckStatus>,
+}
+
+impl PendingAck {
+    pub fn wait(self) -> AckStatus {
+        self.receiver.recv().unwrap_or(AckStatus::Dropped)
+    }
+}
+
+//Derived Clone would want T: Clone, but only the Sender gets cloned.
+impl<T> Clone for AckSender<T> {
+    fn clone(&self) -> Self {
+        AckSender {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<T> AckSender<T> {
+    //Hands the value back in the error if the receiver is gone, the same as mpsc::Sender::send.
+    pub fn send(&self, value: T) -> Result<PendingAck, mpsc::SendError<T>> {
+        let (status_tx, status_rx) = mpsc::channel();
+        self.sender
+            .send((value, AckHandle { status: status_tx }))
+            .map_err(|mpsc::SendError((value, _))| mpsc::SendError(value))?;
+        Ok(PendingAck {
+            receiver: status_rx,
+        })
+    }
+}
+
+impl<T> AckReceiver<T> {
+    pub fn recv(&self) -> Result<(T, AckHandle), mpsc::RecvError> {
+        self.receiver.recv()
+    }
+}
+
+pub fn acknowledging_messages() {
+    let (sender, receiver) = ack_channel::<i32>();
+
+    //The loop ends on its own once the producer's sender is dropped below.
+    let consumer = thread::spawn(move || {
+        while let Ok((value, ack)) = receiver.recv() {
+            match value {
+                v if v < 0 => ack.nack(),
+                0 => drop(ack),
+                _ => ack.ack(),
+            }
+        }
+    });
+
+    let pending: Vec<(i32, PendingAck)> = [5, -1, 0]
+        .into_iter()
+        .map(|value| (value, sender.send(value).expect("Consumer hung up")))
+        .collect();
+    drop(sender);
+    for (value, ack) in pending {
+        println!("message {value} was answered with {:?}", ack.wait());
+    }
+
+    consumer.join().expect("Consumer crashed");
+}
+
+//A promise is the writing end of a value that doesn't exist yet and the future is the reading
+// end. Underneath it is a oneshot channel, the same thing JobHandle uses. This Future has nothing
+// to do with std::future::Future, there is no executor, get() just blocks the thread.
+pub fn promise<T>() -> (Promise<T>, Future<T>) {
+    let (sender, receiver) = mpsc::channel();
+    (Promise { sender }, Future { receiver })
+}
+
+pub struct Promise<T> {
+    sender: mpsc::Sender<T>,
+}
+
+pub struct Future<T> {
+    receiver: mpsc::Receiver<T>,
+}
+
+#[derive(Debug)]
+pub struct BrokenPromise;
+
+impl<T> Promise<T> {
+    //Taking self by value is what makes a promise resolvable only once. If the Future was already
+    // dropped nobody is waiting for the value, so the send error is ignored.
+    pub fn resolve(self, value: T) {
+        let _ = self.sender.send(value);
+    }
+}
+
+impl<T> Future<T> {
+    //Dropping the Promise without resolving it drops the sender, which wakes get() up with an
+    // error instead of leaving it blocked forever.
+    pub fn get(self) -> Result<T, BrokenPromise> {
+        self.receiver.recv().map_err(|_| BrokenPromise)
+    }
+}
+
+pub fn resolving_promises() {
+    let (kept, future) = promise();
+    let resolver = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        kept.resolve(6 * 7);
+    });
+    println!("future after the promise was resolved: {:?}", future.get());
+    resolver.join().expect("Resolver crashed");
+
+    let (broken, future) = promise::<i32>();
+    let forgetful = thread::spawn(move || {
+        let _broken = broken;
+        thread::sleep(Duration::from_millis(10));
+    });
+    println!("future after the promise was dropped: {:?}", future.get());
+    forgetful.join().expect("Forgetful thread crashed");
+}
+
+//Keeps the mean of the last `window` values read off a channel. The consumer thread is the only
+// one that ever changes the ring of values, but current() can be called from any thread, which is
+// what the Arc<Mutex<T>> is for. The mean is worked out from the ring when it's asked for, rather
+// than kept as a running sum that would slowly pick up floating point error.
+pub struct MovingAverage {
+    recent: Arc<Mutex<VecDeque<f64>>>,
+    consumer: Option<thread::JoinHandle<()>>,
+}
+
+impl MovingAverage {
+    pub fn new(rx: mpsc::Receiver<f64>, window: usize) -> MovingAverage {
+        assert!(window > 0, "An average needs at least one value in its window");
+
+        let recent = Arc::new(Mutex::new(VecDeque::with_capacity(window)));
+        let consumer = {
+            let recent = Arc::clone(&recent);
+            thread::spawn(move || {
+                for value in rx {
+                    let mut recent = recent.lock().unwrap();
+                    if recent.len() == window {
+                        recent.pop_front();
+                    }
+                    recent.push_back(value);
+                }
+            })
+        };
+
+        MovingAverage {
+            recent,
+            consumer: Some(consumer),
+        }
+    }
+
+    //None until the first value has arrived.
+    pub fn current(&self) -> Option<f64> {
+        let recent = self.recent.lock().unwrap();
+        if recent.is_empty() {
+            return None;
+        }
+        Some(recent.iter().sum::<f64>() / recent.len() as f64)
+    }
+
+    //Waits for every sender to hang up and returns the average over the final window.
+    pub fn finish(mut self) -> Option<f64> {
+        if let Some(consumer) = self.consumer.take() {
+            consumer.join().expect("Moving average consumer crashed");
+        }
+        self.current()
+    }
+}
+
+pub fn averaging_a_stream() {
+    let (tx, rx) = mpsc::channel();
+    let average = MovingAverage::new(rx, 3);
+    println!("moving average before any values: {:?}", average.current());
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            for value in [1.0, 2.0, 3.0, 4.0] {
+                tx.send(value).expect("Moving average hung up");
+            }
+        });
+
+        //Read from a different thread while values are still arriving.
+        s.spawn(|| println!("moving average mid stream: {:?}", average.current()));
+    });
+
+    tx.send(4.0).expect("Moving average hung up");
+    tx.send(10.0).expect("Moving average hung up");
+    drop(tx);
+
+    //The last 3 values are 4, 4 and 10.
+    println!("moving average over the final window: {:?}, expected 6", average.finish());
+}
+
+//Two output channels instead of one channel of Result<T, E>. Whatever reads the successes never
+// has to look at an error and the errors can go somewhere else entirely, like a log. Each worker
+// gets its own clone of `f`. Both channels are unbounded, so once the scope has joined every
+// worker the senders are all gone and the two channels can simply be drained one after the other.
+// Results come out in whatever order the workers finished them.
+pub fn process_with_errors(
+    items: Vec<i32>,
+    f: impl Fn(i32) -> Result<i32, String> + Send + Sync + Clone,
+) -> (Vec<i32>, Vec<String>) {
+    let (ok_tx, ok_rx) = mpsc::channel();
+    let (err_tx, err_rx) = mpsc::channel();
+
+    thread::scope(|s| {
+        for chunk in split_into_chunks(items, available_threads()) {
+            let (f, ok_tx, err_tx) = (f.clone(), ok_tx.clone(), err_tx.clone());
+            s.spawn(move || {
+                for item in chunk {
+                    let sent = match f(item) {
+                        Ok(value) => ok_tx.send(value).is_ok(),
+                        Err(error) => err_tx.send(error).is_ok(),
+                    };
+                    assert!(sent, "Main thread hung up");
+                }
+            });
+        }
+    });
+    drop((ok_tx, err_tx));
+
+    (ok_rx.into_iter().collect(), err_rx.into_iter().collect())
+}
+
+pub fn splitting_off_errors() {
+    let (mut successes, errors) = process_with_errors(vec![4, -1, 9, 16, -25, 0], |value| {
+        if value < 0 {
+            Err(format!("can't take the square root of {value}"))
+        } else {
+            Ok(value.isqrt())
+        }
+    });
+
+    successes.sort();
+    println!("successes: {:?}", successes);
+    println!("errors: {:?}", errors);
+}
+
+//Wraps a receiver so the next message can be looked at without taking it. At most one message is
+// ever held back, in a OnceCell. A OnceCell can be filled through &self and hands out a plain &T
+// that lives as long as the borrow of self, which is exactly what peek() needs. Nothing can
+// replace the value while that &T is around, because emptying the cell again takes &mut self.
+pub struct PeekableChannel<T> {
+    receiver: mpsc::Receiver<T>,
+    peeked: OnceCell<T>,
+}
+
+impl<T> PeekableChannel<T> {
+    pub fn new(receiver: mpsc::Receiver<T>) -> PeekableChannel<T> {
+        PeekableChannel {
+            receiver,
+            peeked: OnceCell::new(),
+        }
+    }
+
+    //Blocks until there is a message to look at. None means every sender is gone and nothing is
+    // left.
+    pub fn peek(&self) -> Option<&T> {
+        if self.peeked.get().is_none() {
+            let value = self.receiver.recv().ok()?;
+            let _ = self.peeked.set(value);
+        }
+        self.peeked.get()
+    }
+
+    pub fn recv(&mut self) -> Result<T, mpsc::RecvError> {
+        match self.peeked.take() {
+            Some(value) => Ok(value),
+            None => self.receiver.recv(),
+        }
+    }
+}
+
+pub fn peeking_at_the_next_message() {
+    let (tx, rx) = mpsc::channel();
+    let mut channel = PeekableChannel::new(rx);
+
+    thread::spawn(move || {
+        for message in ["first", "second"] {
+            tx.send(message).expect("Receiver hung up");
+        }
+    });
+
+    println!("peek: {:?}, peek again: {:?}", channel.peek(), channel.peek());
+    println!("recv: {:?}", channel.recv());
+    println!("peek: {:?}, recv: {:?}", channel.peek().copied(), channel.recv());
+    println!("peek once every sender is gone: {:?}", channel.peek());
+}
+
+//Lock step flow control. The producer sends one item and then blocks on the ack channel until
+// the consumer says it's done with it, so there is never more than one item in flight no matter
+// how slow the consumer is. A sync_channel(0) gets part of the way there, but it only waits for
+// the consumer to take the item, not to finish processing it. Returns the items in the order the
+// consumer processed them.
+pub fn ack_flow(items: Vec<i32>) -> Vec<i32> {
+    ack_flow_with(items, |_| {})
+}
+
+//The consumer calls `process` on every item before acking it. Taking any iterator lets a test see
+// when the producer pulls the next item.
+fn ack_flow_with<I, F>(items: I, mut process: F) -> Vec<i32>
+where
+    I: IntoIterator<Item = i32>,
+    F: FnMut(i32) + Send + 'static,
+{
+    let (item_tx, item_rx) = mpsc::channel();
+    let (ack_tx, ack_rx) = mpsc::channel::<()>();
+
+    let consumer = thread::spawn(move || {
+        let mut processed = Vec::new();
+        for item in item_rx {
+            process(item);
+            processed.push(item);
+            ack_tx.send(()).expect("Producer hung up");
+        }
+        processed
+    });
+
+    for item in items {
+        item_tx.send(item).expect("Consumer hung up");
+        ack_rx.recv().expect("Consumer hung up before acking");
+    }
+    drop(item_tx);
+
+    consumer.join().expect("Consumer crashed")
+}
+
+pub fn lock_step_flow_control() {
+    let items = vec![5, 3, 8, 1, 9];
+    let processed = ack_flow(items.clone());
+    println!(
+        "lock step processed {:?}, every item once and in order: {}",
+        processed,
+        processed == items
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_producer_keeps_its_own_order() {
+        let producers: Vec<Vec<u32>> = (0..4u32)
+            .map(|id| (0..20).map(|i| id * 100 + i).collect())
+            .collect();
+
+        let collected = collect_preserving_producer_order(producers.clone());
+
+        assert_eq!(collected.len(), producers.len());
+        for (id, items) in producers.iter().enumerate() {
+            assert_eq!(&collected[&id], items);
+        }
+    }
+
+    #[test]
+    fn latency_stats_cover_every_message() {
+        let (tx, mut rx) = timed_channel();
+        assert!(rx.stats().is_none());
+
+        let producer = thread::spawn(move || {
+            for i in 0..5 {
+                tx.send(i).unwrap();
+            }
+        });
+        producer.join().unwrap();
+
+        let delay = Duration::from_millis(20);
+        thread::sleep(delay);
+        let received: Vec<i32> = std::iter::from_fn(|| rx.recv().ok()).collect();
+        assert_eq!(received, vec![0, 1, 2, 3, 4]);
+
+        let stats = rx.stats().unwrap();
+        assert_eq!(stats.count, 5);
+        assert!(stats.min <= stats.mean && stats.mean <= stats.max, "{stats:?}");
+        assert!(stats.min >= delay, "{stats:?}");
+    }
+
+    #[test]
+    fn nobody_passes_the_channel_barrier_early() {
+        let barrier = channel_barrier(5);
+        let arrived = Arc::new(AtomicUsize::new(0));
+
+        //Every thread records how many had arrived when it got through, which has to be all of
+        // them. Two rounds check that the barrier resets itself.
+        let handles: Vec<_> = (0..5)
+            .map(|_| {
+                let barrier = barrier.clone();
+                let arrived = Arc::clone(&arrived);
+                thread::spawn(move || {
+                    let mut seen = Vec::new();
+                    for round in 1..=2 {
+                        arrived.fetch_add(1, Ordering::SeqCst);
+                        barrier();
+                        seen.push(arrived.load(Ordering::SeqCst) >= 5 * round);
+                    }
+                    seen
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), vec![true, true]);
+        }
+    }
+
+    #[test]
+    fn owned_buffers_arrive_whole() {
+        assert_eq!(transfer_ownership_demo(), 3 + 5 + 8);
+    }
+
+    #[test]
+    fn ownership_round_trips_through_channels() {
+        let (to_worker, worker_rx) = mpsc::channel::<Vec<u8>>();
+        let (to_main, main_rx) = mpsc::channel::<Vec<u8>>();
+
+        let worker = thread::spawn(move || {
+            for mut buffer in worker_rx {
+                buffer.push(b'!');
+                to_main.send(buffer).unwrap();
+            }
+        });
+
+        let mut buffer = Vec::with_capacity(16);
+        buffer.extend_from_slice(b"hello");
+        let heap_address = buffer.as_ptr();
+        to_worker.send(buffer).unwrap();
+
+        //The same heap allocation comes back, it was moved both ways rather than copied.
+        let returned = main_rx.recv().unwrap();
+        assert_eq!(returned, b"hello!");
+        assert_eq!(returned.as_ptr(), heap_address);
+
+        drop(to_worker);
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn kway_merge_is_sorted_and_complete() {
+        let inputs = vec![vec![1, 4, 7, 10], vec![], vec![2, 5, 8], vec![0, 3, 3, 6, 9, 12]];
+        let mut expected: Vec<i32> = inputs.iter().flatten().copied().collect();
+        expected.sort();
+
+        assert_eq!(kway_merge(inputs), expected);
+        assert_eq!(kway_merge(vec![]), Vec::<i32>::new());
+        assert_eq!(kway_merge(vec![vec![1, 4, 9]]), vec![1, 4, 9]);
+    }
+
+    #[test]
+    fn windowed_sum_buckets_values_by_arrival_time() {
+        let (tx, rx) = mpsc::channel();
+
+        //Sends land in the middle of their windows so a bit of scheduling delay can't move them.
+        let producer = thread::spawn(move || {
+            for value in [1, 2, 3] {
+                tx.send(value).unwrap();
+            }
+            thread::sleep(Duration::from_millis(300));
+            for value in [10, 20] {
+                tx.send(value).unwrap();
+            }
+        });
+
+        assert_eq!(windowed_sum(rx, Duration::from_millis(200)), vec![6, 30]);
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn quiet_windows_sum_to_zero() {
+        let (tx, rx) = mpsc::channel();
+
+        let producer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(250));
+            tx.send(5).unwrap();
+        });
+
+        assert_eq!(windowed_sum(rx, Duration::from_millis(100)), vec![0, 0, 5]);
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn with_timeout_gives_up_on_slow_work_only() {
+        let fast = with_timeout(Duration::from_secs(5), || (1..=10).product::<u64>());
+        assert_eq!(fast.unwrap(), 3_628_800);
+
+        let slow = with_timeout(Duration::from_millis(20), || {
+            thread::sleep(Duration::from_millis(500));
+            "finally done"
+        });
+        assert!(matches!(slow, Err(Timeout)));
+    }
+
+    #[test]
+    fn producer_sees_each_ack_and_nack() {
+        let (sender, receiver) = ack_channel();
+        let consumer = thread::spawn(move || {
+            let mut answered = 0;
+            while let Ok((value, ack)) = receiver.recv() {
+                match value {
+                    v if v < 0 => ack.nack(),
+                    0 => drop(ack),
+                    _ => ack.ack(),
+                }
+                answered += 1;
+            }
+            answered
+        });
+
+        let acked = sender.send(5).unwrap();
+        let nacked = sender.send(-1).unwrap();
+        let dropped = sender.send(0).unwrap();
+        drop(sender);
+
+        assert_eq!(acked.wait(), AckStatus::Acked);
+        assert_eq!(nacked.wait(), AckStatus::Nacked);
+        assert_eq!(dropped.wait(), AckStatus::Dropped);
+        //Only returns because recv() fails once the sender is gone.
+        assert_eq!(consumer.join().unwrap(), 3);
+    }
+
+    #[test]
+    fn send_fails_once_the_receiver_is_gone() {
+        let (sender, receiver) = ack_channel();
+        drop(receiver);
+        match sender.send(7) {
+            Err(mpsc::SendError(value)) => assert_eq!(value, 7),
+            Ok(_) => panic!("send() should fail without a receiver"),
+        }
+    }
+
+    #[test]
+    fn future_gets_the_resolved_value() {
+        let (kept, future) = promise();
+        let resolver = thread::spawn(move || kept.resolve(String::from("forty two")));
+        assert_eq!(future.get().unwrap(), "forty two");
+        resolver.join().unwrap();
+    }
+
+    #[test]
+    fn dropped_promise_breaks_the_future() {
+        let (broken, future) = promise::<i32>();
+        let forgetful = thread::spawn(move || drop(broken));
+        assert!(matches!(future.get(), Err(BrokenPromise)));
+        forgetful.join().unwrap();
+
+        //Resolving after the future is gone is quietly ignored.
+        let (late, future) = promise();
+        drop(future);
+        late.resolve(1);
+    }
+
+    //Polls current() from its own thread until it shows `expected`.
+    fn wait_for_average(average: &MovingAverage, expected: f64) {
+        thread::scope(|s| {
+            s.spawn(|| {
+                let deadline = Instant::now() + Duration::from_secs(5);
+                while average.current() != Some(expected) {
+                    assert!(Instant::now() < deadline, "Average stuck at {:?}", average.current());
+                    thread::sleep(Duration::from_millis(1));
+                }
+            });
+        });
+    }
+
+    #[test]
+    fn moving_average_is_visible_from_other_threads() {
+        let (tx, rx) = mpsc::channel();
+        let average = MovingAverage::new(rx, 3);
+        assert_eq!(average.current(), None);
+
+        for value in [1.0, 2.0, 3.0] {
+            tx.send(value).unwrap();
+        }
+        wait_for_average(&average, 2.0);
+
+        //The window only holds the last three, so 1.0 falls out.
+        tx.send(4.0).unwrap();
+        wait_for_average(&average, 3.0);
+
+        tx.send(11.0).unwrap();
+        drop(tx);
+        assert_eq!(average.finish(), Some(6.0));
+    }
+
+    #[test]
+    fn successes_and_errors_go_to_their_own_channels() {
+        let (mut successes, mut errors) =
+            process_with_errors(vec![4, -1, 9, 16, -25, 0, 1], |value| {
+                if value < 0 {
+                    Err(format!("negative {value}"))
+                } else {
+                    Ok(value.isqrt())
+                }
+            });
+
+        successes.sort();
+        errors.sort();
+        assert_eq!(successes, vec![0, 1, 2, 3, 4]);
+        assert_eq!(errors, vec!["negative -1", "negative -25"]);
+
+        let (successes, errors) = process_with_errors((0..1_000).collect(), Ok);
+        assert_eq!(successes.len(), 1_000);
+        assert!(errors.is_empty());
+        assert_eq!(process_with_errors(Vec::new(), Ok), (Vec::new(), Vec::new()));
+    }
+
+    #[test]
+    fn receive_loop_ends_once_the_last_sender_is_dropped() {
+        //Run on a helper thread so a loop that never ends fails the test instead of hanging it.
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::spawn(move || done_tx.send(sender_lifecycle_demo()).unwrap());
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(10)), Ok(5));
+
+        let (tx, rx) = mpsc::channel::<u32>();
+        let clones: Vec<_> = (0..3).map(|_| tx.clone()).collect();
+        let receiver = thread::spawn(move || rx.into_iter().count());
+
+        drop(tx);
+        for clone in clones {
+            thread::sleep(Duration::from_millis(20));
+            assert!(!receiver.is_finished(), "The loop ended while a sender was still alive");
+            clone.send(1).unwrap();
+        }
+        assert_eq!(receiver.join().unwrap(), 3);
+    }
+
+    #[test]
+    fn peek_does_not_consume() {
+        let (tx, rx) = mpsc::channel();
+        let mut channel = PeekableChannel::new(rx);
+        for message in ["first", "second", "third"] {
+            tx.send(message).unwrap();
+        }
+        drop(tx);
+
+        assert_eq!(channel.peek(), Some(&"first"));
+        assert_eq!(channel.peek(), Some(&"first"));
+        assert_eq!(channel.recv(), Ok("first"));
+
+        //recv() without a peek first still takes the next message in order.
+        assert_eq!(channel.recv(), Ok("second"));
+        assert_eq!(channel.peek(), Some(&"third"));
+        assert_eq!(channel.recv(), Ok("third"));
+
+        assert_eq!(channel.peek(), None);
+        assert_eq!(channel.recv(), Err(mpsc::RecvError));
+    }
+
+    #[test]
+    fn ack_flow_processes_everything_once_and_in_order() {
+        let items = vec![5, 3, 8, 1, 9, 3];
+        assert_eq!(ack_flow(items.clone()), items);
+        assert!(ack_flow(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn producer_is_never_more_than_one_item_ahead() {
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let ahead = Arc::new(Mutex::new(Vec::new()));
+
+        let items = {
+            let pulled = Arc::clone(&pulled);
+            (0..50).inspect(move |_| {
+                pulled.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        let processed = {
+            let (pulled, ahead) = (Arc::clone(&pulled), Arc::clone(&ahead));
+            let mut done = 0;
+            ack_flow_with(items, move |_| {
+                //A slow consumer gives a producer that doesn't wait time to run ahead.
+                thread::sleep(Duration::from_millis(1));
+                ahead.lock().unwrap().push(pulled.load(Ordering::SeqCst) - done);
+                done += 1;
+            })
+        };
+
+        assert_eq!(processed, (0..50).collect::<Vec<_>>());
+        //While item n is being processed the producer has pulled items 0 to n and nothing more.
+        assert_eq!(*ahead.lock().unwrap(), vec![1; 50]);
+    }
+}
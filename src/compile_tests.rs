@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+//Each of the commented out blocks below would be a data race if Rust allowed it, and each one is
+// rejected at compile time. The error code the compiler gives is listed above every block. Under
+// each block is the version that the compiler does accept, which is what actually runs.
+//Each block is also a trybuild compile-fail case in tests/ui, so `cargo test` checks that rustc
+// still rejects it with the error in the matching .stderr file.
+pub fn data_races_the_compiler_prevents() {
+    //1) Two threads mutably borrowing the same value.
+    // error[E0499]: cannot borrow `counter` as mutable more than once at a time
+    //
+    // let mut counter = 0;
+    // thread::scope(|s| {
+    //     s.spawn(|| counter += 1);
+    //     s.spawn(|| counter += 1);
+    // });
+    let counter = Mutex::new(0);
+    thread::scope(|s| {
+        s.spawn(|| *counter.lock().unwrap() += 1);
+        s.spawn(|| *counter.lock().unwrap() += 1);
+    });
+    println!("counter behind a Mutex: {}", counter.into_inner().unwrap());
+
+    //2) A spawned thread borrowing a local that could be dropped before the thread finishes.
+    // error[E0373]: closure may outlive the current function, but it borrows `greeting`, which
+    //  is owned by the current function
+    //
+    // let greeting = String::from("hello");
+    // let handle = thread::spawn(|| println!("{greeting}"));
+    // handle.join().unwrap();
+    let greeting = String::from("hello");
+    let handle = thread::spawn(move || greeting.len());
+    println!("length of moved greeting: {}", handle.join().expect("Greeting thread crashed"));
+
+    //3) Sending an Rc<T> to another thread. The reference count is not atomic, so two threads
+    // cloning or dropping it at the same time could corrupt the count.
+    // error[E0277]: `Rc<i32>` cannot be sent between threads safely
+    //
+    // let shared = Rc::new(5);
+    // let handle = thread::spawn(move || println!("{shared}"));
+    // handle.join().unwrap();
+    let shared = Arc::new(5);
+    let shared_clone = Arc::clone(&shared);
+    let handle = thread::spawn(move || *shared_clone * 2);
+    println!("Arc instead of Rc: {}", handle.join().expect("Arc thread crashed"));
+
+    //4) Sharing a RefCell<T> between threads. RefCell<T> is Send but not Sync, its borrow
+    // tracking is not thread safe.
+    // error[E0277]: `RefCell<i32>` cannot be shared between threads safely
+    //
+    // let cell = RefCell::new(0);
+    // thread::scope(|s| {
+    //     s.spawn(|| *cell.borrow_mut() += 1);
+    //     s.spawn(|| *cell.borrow_mut() += 1);
+    // });
+    let cell = AtomicI32::new(0);
+    thread::scope(|s| {
+        s.spawn(|| cell.fetch_add(1, Ordering::SeqCst));
+        s.spawn(|| cell.fetch_add(1, Ordering::SeqCst));
+    });
+    println!("atomic instead of RefCell: {}", cell.load(Ordering::SeqCst));
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn data_races_fail_to_compile() {
+        let cases = trybuild::TestCases::new();
+        cases.compile_fail("tests/ui/*.rs");
+    }
+}
@@ -0,0 +1,840 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, RandomState};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Barrier, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//Handlers are stored behind an Arc so that get() can hand out a copy without holding the lock
+// while the handler runs.
+pub type Handler = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+#[derive(Debug)]
+pub struct AlreadyRegistered(pub String);
+
+//A name can be set once and never replaced. The check for an existing name and the insert both
+// happen under the same lock, which is what makes a race between two threads registering the
+// same name safe. If the check and the insert were two separate lock() calls, both threads could
+// see the name as missing and both would think they won.
+#[derive(Default)]
+pub struct HandlerRegistry {
+    handlers: Mutex<HashMap<String, Handler>>,
+}
+
+impl HandlerRegistry {
+    pub fn new() -> HandlerRegistry {
+        HandlerRegistry::default()
+    }
+
+    pub fn register<F>(&self, name: &str, handler: F) -> Result<(), AlreadyRegistered>
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        let mut handlers = self.handlers.lock().unwrap();
+
+        match handlers.entry(name.to_string()) {
+            Entry::Occupied(_) => Err(AlreadyRegistered(name.to_string())),
+            Entry::Vacant(entry) => {
+                entry.insert(Arc::new(handler));
+                Ok(())
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Handler> {
+        self.handlers.lock().unwrap().get(name).cloned()
+    }
+}
+
+pub fn registering_handlers_from_multiple_threads() {
+    let registry = Arc::new(HandlerRegistry::new());
+
+    //The barrier lines both threads up so that they really do try to register at the same time.
+    let barrier = Arc::new(Barrier::new(2));
+
+    let racers: Vec<_> = ["first", "second"]
+        .into_iter()
+        .map(|racer| {
+            let registry = Arc::clone(&registry);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                let result = registry.register("greet", move |input| {
+                    format!("{racer} says hello to {input}")
+                });
+                (racer, result)
+            })
+        })
+        .collect();
+
+    for racer in racers {
+        match racer.join().expect("Racer thread crashed") {
+            (racer, Ok(())) => println!("{racer} registered the handler"),
+            (racer, Err(AlreadyRegistered(name))) => {
+                println!("{racer} lost the race, \"{name}\" was already registered")
+            }
+        }
+    }
+
+    if let Some(handler) = registry.get("greet") {
+        println!("{}", handler("the registry"));
+    }
+    println!("missing handler: {}", registry.get("missing").is_none());
+}
+
+const COUNTER_MAP_STRIPES: usize = 16;
+
+//Counting with one Mutex<HashMap> means every increment from every thread fights over the same
+// lock. Lock striping splits the map into several smaller maps, each with its own lock, and a key
+// always goes to the same stripe based on its hash. Two threads only contend when their keys
+// happen to land in the same stripe.
+pub struct ConcurrentCounterMap {
+    hasher: RandomState,
+    stripes: Vec<Mutex<HashMap<String, u64>>>,
+}
+
+impl Default for ConcurrentCounterMap {
+    fn default() -> Self {
+        ConcurrentCounterMap {
+            hasher: RandomState::new(),
+            stripes: (0..COUNTER_MAP_STRIPES).map(|_| Mutex::default()).collect(),
+        }
+    }
+}
+
+impl ConcurrentCounterMap {
+    pub fn new() -> ConcurrentCounterMap {
+        ConcurrentCounterMap::default()
+    }
+
+    fn stripe(&self, key: &str) -> &Mutex<HashMap<String, u64>> {
+        let index = self.hasher.hash_one(key) as usize % self.stripes.len();
+        &self.stripes[index]
+    }
+
+    pub fn increment(&self, key: &str) {
+        let mut stripe = self.stripe(key).lock().unwrap();
+
+        //Looking the key up first avoids allocating a String on every increment.
+        match stripe.get_mut(key) {
+            Some(count) => *count += 1,
+            None => {
+                stripe.insert(key.to_string(), 1);
+            }
+        }
+    }
+
+    //The stripes are locked one at a time, so if increments are still happening the snapshot
+    // might include some of them and not others. Once every writer is done it is exact.
+    pub fn snapshot(&self) -> HashMap<String, u64> {
+        let mut snapshot = HashMap::new();
+        for stripe in &self.stripes {
+            for (key, count) in stripe.lock().unwrap().iter() {
+                snapshot.insert(key.clone(), *count);
+            }
+        }
+        snapshot
+    }
+}
+
+pub fn counting_events_by_key() {
+    let counters = ConcurrentCounterMap::new();
+    let keys = ["click", "scroll", "keypress"];
+
+    //Every thread increments every key, thread i doing it (i + 1) * 100 times.
+    thread::scope(|s| {
+        for i in 0..4 {
+            let counters = &counters;
+            s.spawn(move || {
+                for _ in 0..(i + 1) * 100 {
+                    for key in keys {
+                        counters.increment(key);
+                    }
+                }
+            });
+        }
+    });
+
+    let mut totals: Vec<_> = counters.snapshot().into_iter().collect();
+    totals.sort();
+    println!("event counts (expected 1000 each): {:?}", totals);
+}
+
+//Hands out resources that are expensive to create (think database connections) and takes them
+// back when the caller is done, instead of creating a new one every time. When the pool is empty
+// acquire() sleeps on the condvar until somebody gives a resource back.
+pub struct ResourcePool<T> {
+    resources: Mutex<Vec<T>>,
+    returned: Condvar,
+}
+
+impl<T> ResourcePool<T> {
+    pub fn new(resources: Vec<T>) -> ResourcePool<T> {
+        ResourcePool {
+            resources: Mutex::new(resources),
+            returned: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self) -> PooledResource<'_, T> {
+        let resources = self.resources.lock().unwrap();
+        let mut resources = self
+            .returned
+            .wait_while(resources, |resources| resources.is_empty())
+            .unwrap();
+
+        PooledResource {
+            pool: self,
+            resource: resources.pop(),
+        }
+    }
+}
+
+//The resource lives in an Option so that drop() can move it back into the pool. It is only None
+// for the short moment inside drop().
+pub struct PooledResource<'a, T> {
+    pool: &'a ResourcePool<T>,
+    resource: Option<T>,
+}
+
+impl<T> Deref for PooledResource<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.resource.as_ref().expect("Resource is only taken on drop")
+    }
+}
+
+impl<T> DerefMut for PooledResource<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.resource.as_mut().expect("Resource is only taken on drop")
+    }
+}
+
+impl<T> Drop for PooledResource<'_, T> {
+    fn drop(&mut self) {
+        if let Some(resource) = self.resource.take() {
+            self.pool.resources.lock().unwrap().push(resource);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+pub fn reusing_pooled_resources() {
+    let pool = ResourcePool::new(vec![String::from("connection 1"), String::from("connection 2")]);
+
+    let first = pool.acquire();
+    let second = pool.acquire();
+    println!("holding {} and {}", *first, *second);
+
+    thread::scope(|s| {
+        let waiter = s.spawn(|| {
+            let started = Instant::now();
+            let third = pool.acquire();
+            println!("third acquire() got {} after {:?}", *third, started.elapsed());
+        });
+
+        thread::sleep(Duration::from_millis(50));
+        drop(first);
+        waiter.join().expect("Waiting thread crashed");
+    });
+
+    drop(second);
+}
+
+//A bounded last-in-first-out stack that blocks instead of failing. pop() waits while it is empty
+// and push() waits while it is full. Two condvars are used so that a push only wakes up threads
+// waiting to pop and a pop only wakes up threads waiting to push.
+pub struct BlockingStack<T> {
+    items: Mutex<Vec<T>>,
+    capacity: usize,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+impl<T> BlockingStack<T> {
+    pub fn new(capacity: usize) -> BlockingStack<T> {
+        assert!(capacity > 0, "A stack with no room would block every push");
+
+        BlockingStack {
+            items: Mutex::new(Vec::with_capacity(capacity)),
+            capacity,
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, item: T) {
+        let items = self.items.lock().unwrap();
+        let mut items = self
+            .not_full
+            .wait_while(items, |items| items.len() == self.capacity)
+            .unwrap();
+        items.push(item);
+        self.not_empty.notify_one();
+    }
+
+    pub fn pop(&self) -> T {
+        let items = self.items.lock().unwrap();
+        let mut items = self
+            .not_empty
+            .wait_while(items, |items| items.is_empty())
+            .unwrap();
+        let item = items.pop().expect("Waited until the stack wasn't empty");
+        self.not_full.notify_one();
+        item
+    }
+}
+
+pub fn blocking_stack_push_and_pop() {
+    let stack = BlockingStack::new(3);
+    for i in 1..=3 {
+        stack.push(i);
+    }
+    println!("blocking stack pops: {}, {}, {}", stack.pop(), stack.pop(), stack.pop());
+
+    //With a tiny capacity the producers and consumers keep blocking on each other, so this checks
+    // that nothing gets lost or duplicated along the way.
+    let stack = BlockingStack::new(4);
+    let popped_sum: u64 = thread::scope(|s| {
+        for producer in 0..4u64 {
+            let stack = &stack;
+            s.spawn(move || {
+                for i in 0..250 {
+                    stack.push(producer * 250 + i);
+                }
+            });
+        }
+
+        let consumers: Vec<_> = (0..2)
+            .map(|_| s.spawn(|| (0..500).map(|_| stack.pop()).sum::<u64>()))
+            .collect();
+
+        consumers
+            .into_iter()
+            .map(|consumer| consumer.join().expect("Consumer crashed"))
+            .sum()
+    });
+    println!(
+        "blocking stack sum of popped items: {popped_sum}, expected {}",
+        (0..1000u64).sum::<u64>()
+    );
+}
+
+//Bucket i counts the values that are <= bounds[i] and bigger than the bound before it. There is
+// one extra bucket at the end for everything above the last bound. The set of buckets never
+// changes after creation, so every bucket can just be an AtomicU64 and record() never needs a
+// lock, it is one fetch_add() on the right bucket.
+pub struct Histogram {
+    bounds: Vec<u64>,
+    buckets: Vec<AtomicU64>,
+}
+
+impl Histogram {
+    pub fn new(mut bounds: Vec<u64>) -> Histogram {
+        bounds.sort_unstable();
+        bounds.dedup();
+        let buckets = (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect();
+
+        Histogram { bounds, buckets }
+    }
+
+    pub fn record(&self, value: u64) {
+        let bucket = self.bounds.partition_point(|&bound| bound < value);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    //Each bucket is read on its own, so a snapshot taken while values are being recorded might
+    // not add up to an exact point in time. After the writers are done it is exact.
+    pub fn snapshot(&self) -> Vec<u64> {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect()
+    }
+}
+
+pub fn recording_into_an_atomic_histogram() {
+    let histogram = Histogram::new(vec![10, 100, 1000]);
+
+    //Thread t records the values t * 1000 .. t * 1000 + 1000, so together they cover 0..4000.
+    thread::scope(|s| {
+        for t in 0..4u64 {
+            let histogram = &histogram;
+            s.spawn(move || {
+                for value in t * 1000..(t + 1) * 1000 {
+                    histogram.record(value);
+                }
+            });
+        }
+    });
+
+    let snapshot = histogram.snapshot();
+    println!(
+        "histogram buckets for <=10, <=100, <=1000, above: {:?}, total: {}",
+        snapshot,
+        snapshot.iter().sum::<u64>()
+    );
+}
+
+//Optimistic concurrency. Instead of holding a lock for the whole read, modify, write, a thread
+// reads a copy along with its version, works on the copy without any lock, then asks for the
+// update to be applied only if nobody else got there first. The version goes up by one on every
+// successful update, so a changed version means the copy is stale and the work has to be redone
+// against a fresh read.
+pub struct Versioned<T: Clone> {
+    state: Mutex<(u64, T)>,
+}
+
+//The value has moved on since it was read. `current` is the version it is at now.
+#[derive(Debug)]
+pub struct CasConflict {
+    pub current: u64,
+}
+
+impl<T: Clone> Versioned<T> {
+    pub fn new(value: T) -> Versioned<T> {
+        Versioned {
+            state: Mutex::new((0, value)),
+        }
+    }
+
+    pub fn read(&self) -> (u64, T) {
+        self.state.lock().unwrap().clone()
+    }
+
+    //Returns the new version on success. The check and the write happen under the same lock, so
+    // two threads holding the same version can never both succeed.
+    pub fn compare_and_set(&self, expected_version: u64, new: T) -> Result<u64, CasConflict> {
+        let mut state = self.state.lock().unwrap();
+        if state.0 != expected_version {
+            return Err(CasConflict { current: state.0 });
+        }
+
+        *state = (expected_version + 1, new);
+        Ok(state.0)
+    }
+}
+
+pub fn racing_compare_and_set() {
+    let shared = Versioned::new(String::from("original"));
+    let (version, _) = shared.read();
+
+    //Both threads read version 0 and then try to update it at the same moment.
+    let barrier = Barrier::new(2);
+    let results: Vec<Result<u64, CasConflict>> = thread::scope(|s| {
+        let handles: Vec<_> = ["from thread a", "from thread b"]
+            .into_iter()
+            .map(|update| {
+                let (shared, barrier) = (&shared, &barrier);
+                s.spawn(move || {
+                    barrier.wait();
+                    shared.compare_and_set(version, update.to_string())
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("CAS thread crashed"))
+            .collect()
+    });
+
+    let successes = results.iter().filter(|result| result.is_ok()).count();
+    println!("racing CAS results: {:?}, {successes} succeeded", results);
+
+    for result in results {
+        if let Err(conflict) = result {
+            println!("the loser saw the value already at version {}", conflict.current);
+        }
+    }
+    println!("value after the race: {:?}", shared.read());
+}
+
+//One bit per index, 64 to a word. fetch_or() sets the bit and returns the whole word as it was,
+// all in one atomic step, so out of any number of threads racing on the same index exactly one
+// sees the bit still clear. That makes test_and_set() a way to claim an index.
+pub struct ConcurrentBitSet {
+    words: Vec<AtomicU64>,
+    len: usize,
+}
+
+impl ConcurrentBitSet {
+    pub fn new(len: usize) -> ConcurrentBitSet {
+        ConcurrentBitSet {
+            words: (0..len.div_ceil(64)).map(|_| AtomicU64::new(0)).collect(),
+            len,
+        }
+    }
+
+    //Returns whether the bit was already set. AcqRel means whoever claims an index also sees
+    // anything written before a bit in the same word was set by another thread.
+    pub fn test_and_set(&self, index: usize) -> bool {
+        assert!(index < self.len, "Index {index} is out of bounds for {} bits", self.len);
+
+        let mask = 1 << (index % 64);
+        self.words[index / 64].fetch_or(mask, Ordering::AcqRel) & mask != 0
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.load(Ordering::Acquire).count_ones() as usize)
+            .sum()
+    }
+}
+
+pub fn claiming_indices_with_a_bitset() {
+    const BITS: usize = 1_000;
+    let bits = ConcurrentBitSet::new(BITS);
+
+    //Every thread tries to claim every index, just starting at a different place.
+    let claims: Vec<Vec<usize>> = thread::scope(|s| {
+        let handles: Vec<_> = (0..4)
+            .map(|thread| {
+                let bits = &bits;
+                s.spawn(move || {
+                    (0..BITS)
+                        .map(|i| (i + thread * 250) % BITS)
+                        .filter(|&index| !bits.test_and_set(index))
+                        .collect::<Vec<usize>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Bitset thread crashed"))
+            .collect()
+    });
+
+    let mut claimed: Vec<usize> = claims.iter().flatten().copied().collect();
+    claimed.sort();
+    let each_claimed_once = claimed == (0..BITS).collect::<Vec<usize>>();
+    println!(
+        "bitset claims per thread: {:?}, every index claimed exactly once: {each_claimed_once}, \
+         bits set: {}",
+        claims.iter().map(Vec::len).collect::<Vec<_>>(),
+        bits.count_ones()
+    );
+}
+
+//fetch_add() hands every caller a different value even when they all call it at the same time,
+// and since the counter only goes up the ids come out in increasing order. Relaxed is enough:
+// uniqueness comes from the read-modify-write being atomic, and nothing else is being published
+// alongside the id.
+pub struct IdGenerator {
+    next: AtomicU64,
+}
+
+//The crate wide generator. AtomicU64::new() is a const fn, so this can be a plain static with no
+// lazy initialization.
+pub static IDS: IdGenerator = IdGenerator::new();
+
+impl IdGenerator {
+    pub const fn new() -> IdGenerator {
+        IdGenerator {
+            next: AtomicU64::new(0),
+        }
+    }
+
+    pub fn next(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+pub fn generating_unique_ids() {
+    let ids: Vec<Vec<u64>> = thread::scope(|s| {
+        let handles: Vec<_> = (0..10)
+            .map(|_| s.spawn(|| (0..1000).map(|_| IDS.next()).collect::<Vec<u64>>()))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Id thread crashed"))
+            .collect()
+    });
+
+    let increasing = ids.iter().all(|ids| ids.windows(2).all(|pair| pair[0] < pair[1]));
+    let unique: HashSet<u64> = ids.into_iter().flatten().collect();
+    println!(
+        "10 threads took {} unique ids, increasing within every thread: {increasing}",
+        unique.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+
+    #[test]
+    fn exactly_one_racer_registers_a_name() {
+        let registry = HandlerRegistry::new();
+        let barrier = Barrier::new(8);
+
+        let wins: Vec<bool> = thread::scope(|s| {
+            let racers: Vec<_> = (0..8)
+                .map(|racer| {
+                    let registry = &registry;
+                    let barrier = &barrier;
+                    s.spawn(move || {
+                        barrier.wait();
+                        registry
+                            .register("greet", move |_| format!("racer {racer}"))
+                            .is_ok()
+                    })
+                })
+                .collect();
+            racers.into_iter().map(|racer| racer.join().unwrap()).collect()
+        });
+
+        assert_eq!(wins.iter().filter(|won| **won).count(), 1);
+        let winner = wins.iter().position(|won| *won).unwrap();
+        assert_eq!(registry.get("greet").unwrap()("anyone"), format!("racer {winner}"));
+    }
+
+    #[test]
+    fn handlers_from_many_threads_are_all_registered() {
+        let registry = HandlerRegistry::new();
+
+        thread::scope(|s| {
+            for i in 0..8 {
+                let registry = &registry;
+                s.spawn(move || {
+                    let name = format!("handler-{i}");
+                    registry
+                        .register(&name, move |input| format!("{i}:{input}"))
+                        .unwrap();
+                });
+            }
+        });
+
+        for i in 0..8 {
+            let handler = registry.get(&format!("handler-{i}")).unwrap();
+            assert_eq!(handler("call"), format!("{i}:call"));
+        }
+        assert!(registry.get("missing").is_none());
+        assert!(matches!(
+            registry.register("handler-0", |input| input.to_string()),
+            Err(AlreadyRegistered(name)) if name == "handler-0"
+        ));
+    }
+
+    #[test]
+    fn counter_map_totals_are_exact_under_contention() {
+        let counters = ConcurrentCounterMap::new();
+        let keys = ["click", "scroll", "keypress", "resize"];
+
+        thread::scope(|s| {
+            for i in 0..4 {
+                let counters = &counters;
+                s.spawn(move || {
+                    for _ in 0..(i + 1) * 100 {
+                        for key in keys {
+                            counters.increment(key);
+                        }
+                    }
+                    counters.increment(&format!("thread-{i}"));
+                });
+            }
+        });
+
+        let totals = counters.snapshot();
+        assert_eq!(totals.len(), keys.len() + 4);
+        for key in keys {
+            assert_eq!(totals[key], 1000, "{key}");
+        }
+        for i in 0..4 {
+            assert_eq!(totals[&format!("thread-{i}")], 1);
+        }
+    }
+
+    #[test]
+    fn acquire_blocks_until_a_resource_is_returned() {
+        let pool = ResourcePool::new(vec![1, 2]);
+        let first = pool.acquire();
+        let second = pool.acquire();
+        let third_acquired = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                let third = pool.acquire();
+                third_acquired.store(true, Ordering::SeqCst);
+                *third
+            });
+
+            thread::sleep(Duration::from_millis(50));
+            assert!(!third_acquired.load(Ordering::SeqCst), "acquire() didn't block");
+
+            let returned = *first;
+            drop(first);
+            assert_eq!(waiter.join().unwrap(), returned);
+        });
+
+        drop(second);
+        let mut left: Vec<i32> = pool.resources.lock().unwrap().clone();
+        left.sort();
+        assert_eq!(left, vec![1, 2]);
+    }
+
+    #[test]
+    fn blocked_pop_wakes_up_on_push() {
+        let stack = BlockingStack::new(2);
+        let popped = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            let popper = s.spawn(|| {
+                let item = stack.pop();
+                popped.store(true, Ordering::SeqCst);
+                item
+            });
+
+            thread::sleep(Duration::from_millis(50));
+            assert!(!popped.load(Ordering::SeqCst), "pop() didn't block on an empty stack");
+
+            stack.push(42);
+            assert_eq!(popper.join().unwrap(), 42);
+        });
+    }
+
+    #[test]
+    fn blocking_stack_is_lifo_and_loses_nothing() {
+        let stack = BlockingStack::new(3);
+        for i in 1..=3 {
+            stack.push(i);
+        }
+        assert_eq!([stack.pop(), stack.pop(), stack.pop()], [3, 2, 1]);
+
+        let stack = BlockingStack::new(4);
+        let mut popped: Vec<u64> = thread::scope(|s| {
+            for producer in 0..4u64 {
+                let stack = &stack;
+                s.spawn(move || {
+                    for i in 0..250 {
+                        stack.push(producer * 250 + i);
+                    }
+                });
+            }
+            let consumers: Vec<_> = (0..2)
+                .map(|_| s.spawn(|| (0..500).map(|_| stack.pop()).collect::<Vec<u64>>()))
+                .collect();
+            consumers.into_iter().flat_map(|consumer| consumer.join().unwrap()).collect()
+        });
+
+        popped.sort();
+        assert_eq!(popped, (0..1000).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn histogram_bucket_totals_from_many_threads() {
+        //Unsorted and with a duplicate, new() sorts and dedups the bounds.
+        let histogram = Histogram::new(vec![1000, 10, 100, 10]);
+
+        thread::scope(|s| {
+            for t in 0..4u64 {
+                let histogram = &histogram;
+                s.spawn(move || {
+                    for value in t * 1000..(t + 1) * 1000 {
+                        histogram.record(value);
+                    }
+                });
+            }
+        });
+
+        //0..=10, 11..=100, 101..=1000 and 1001..4000.
+        assert_eq!(histogram.snapshot(), vec![11, 90, 900, 2999]);
+    }
+
+    #[test]
+    fn exactly_one_compare_and_set_wins_a_version() {
+        let shared = Versioned::new(String::from("original"));
+        let (version, _) = shared.read();
+        let barrier = Barrier::new(8);
+
+        let results: Vec<(usize, Result<u64, CasConflict>)> = thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|id| {
+                    let (shared, barrier) = (&shared, &barrier);
+                    s.spawn(move || {
+                        barrier.wait();
+                        (id, shared.compare_and_set(version, format!("from {id}")))
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        let winners: Vec<usize> =
+            results.iter().filter(|(_, result)| result.is_ok()).map(|(id, _)| *id).collect();
+        assert_eq!(winners.len(), 1);
+        for (_, result) in &results {
+            match result {
+                Ok(new_version) => assert_eq!(*new_version, 1),
+                Err(conflict) => assert_eq!(conflict.current, 1),
+            }
+        }
+        assert_eq!(shared.read(), (1, format!("from {}", winners[0])));
+
+        //A fresh read has the winning version, so retrying with it works.
+        assert_eq!(shared.compare_and_set(1, String::from("retried")).unwrap(), 2);
+    }
+
+    #[test]
+    fn every_bit_is_claimed_exactly_once() {
+        const BITS: usize = 1_000;
+        let bits = ConcurrentBitSet::new(BITS);
+        let barrier = Barrier::new(4);
+
+        let claims: Vec<Vec<usize>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|thread| {
+                    let (bits, barrier) = (&bits, &barrier);
+                    s.spawn(move || {
+                        barrier.wait();
+                        (0..BITS)
+                            .map(|i| (i + thread * 250) % BITS)
+                            .filter(|&index| !bits.test_and_set(index))
+                            .collect::<Vec<usize>>()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        let mut claimed: Vec<usize> = claims.into_iter().flatten().collect();
+        claimed.sort();
+        assert_eq!(claimed, (0..BITS).collect::<Vec<_>>());
+        assert_eq!(bits.count_ones(), BITS);
+        assert!(bits.test_and_set(999), "A claimed bit stays set");
+    }
+
+    #[test]
+    #[should_panic(expected = "Index 64 is out of bounds for 64 bits")]
+    fn bitset_rejects_out_of_bounds_indexes() {
+        ConcurrentBitSet::new(64).test_and_set(64);
+    }
+
+    #[test]
+    fn ids_are_unique_across_threads() {
+        let generator = IdGenerator::new();
+        let ids: Vec<Vec<u64>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..10)
+                .map(|_| s.spawn(|| (0..1000).map(|_| generator.next()).collect::<Vec<u64>>()))
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        for ids in &ids {
+            assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+        let unique: HashSet<u64> = ids.into_iter().flatten().collect();
+        assert_eq!(unique.len(), 10_000);
+        //No gaps either, every id from 0 up was handed out once.
+        assert_eq!(unique, (0..10_000).collect());
+        assert_eq!(generator.next(), 10_000);
+    }
+}
@@ -0,0 +1,745 @@
+use std::cell::UnsafeCell;
+use std::hint;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier, mpsc, Mutex, MutexGuard, TryLockError};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+//The classic ABBA deadlock is thread 1 locking A then B while thread 2 locks B then A. If every
+// thread always locks in the same global order it can't happen. Here the order is the address of
+// each mutex, which is stable for as long as the borrows are alive. The guards are handed back in
+// the order the caller passed the mutexes in, so the caller never has to care about the sorting.
+pub fn lock_all<'a, T>(mutexes: &[&'a Mutex<T>]) -> Vec<MutexGuard<'a, T>> {
+    let address = |index: &usize| mutexes[*index] as *const Mutex<T> as usize;
+
+    let mut lock_order: Vec<usize> = (0..mutexes.len()).collect();
+    lock_order.sort_by_key(address);
+
+    //Locking the same mutex twice from one thread would deadlock (see the comment in
+    // shared_state_concurrency()), so passing a duplicate is treated as a bug.
+    assert!(
+        lock_order.windows(2).all(|pair| address(&pair[0]) != address(&pair[1])),
+        "lock_all() was passed the same mutex more than once"
+    );
+
+    let mut guards: Vec<Option<MutexGuard<'a, T>>> = mutexes.iter().map(|_| None).collect();
+    for index in lock_order {
+        guards[index] = Some(mutexes[index].lock().unwrap());
+    }
+
+    guards
+        .into_iter()
+        .map(|guard| guard.expect("Every mutex was locked"))
+        .collect()
+}
+
+pub fn locking_in_a_consistent_order() {
+    let checking = Mutex::new(1000);
+    let savings = Mutex::new(1000);
+
+    //Both threads ask for the accounts in opposite orders. Locking them one at a time in the
+    // order given would eventually deadlock, lock_all() never does.
+    thread::scope(|s| {
+        s.spawn(|| {
+            for _ in 0..1000 {
+                let mut accounts = lock_all(&[&checking, &savings]);
+                *accounts[0] -= 1;
+                *accounts[1] += 1;
+            }
+        });
+        s.spawn(|| {
+            for _ in 0..1000 {
+                let mut accounts = lock_all(&[&savings, &checking]);
+                *accounts[0] -= 2;
+                *accounts[1] += 2;
+            }
+        });
+    });
+
+    println!(
+        "checking: {}, savings: {}",
+        checking.into_inner().unwrap(),
+        savings.into_inner().unwrap()
+    );
+}
+
+//The dining philosophers problem. Philosopher i needs fork i and fork i + 1 (wrapping around) to
+// eat. If every philosopher grabs their left fork first and then their right fork, they can all
+// end up holding a left fork and waiting forever for the right one. lock_all() always locks the
+// forks in the same global order no matter which one is "left", so that cycle can't happen.
+// Returns how many times each philosopher ate.
+pub fn dining_philosophers(n: usize, rounds: usize) -> Vec<usize> {
+    assert!(n > 1, "You need at least two philosophers to share forks");
+
+    let forks: Vec<Mutex<()>> = (0..n).map(|_| Mutex::new(())).collect();
+    let eat_counts: Vec<AtomicUsize> = (0..n).map(|_| AtomicUsize::new(0)).collect();
+
+    thread::scope(|s| {
+        for philosopher in 0..n {
+            let forks = &forks;
+            let eat_counts = &eat_counts;
+            s.spawn(move || {
+                let left = &forks[philosopher];
+                let right = &forks[(philosopher + 1) % n];
+
+                for _ in 0..rounds {
+                    let _forks = lock_all(&[left, right]);
+                    eat_counts[philosopher].fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+
+    eat_counts.into_iter().map(AtomicUsize::into_inner).collect()
+}
+
+pub fn philosophers_without_deadlock() {
+    println!("philosopher eat counts: {:?}", dining_philosophers(5, 100));
+}
+
+//A spinlock is the simplest lock there is, a single flag that says whether somebody holds it.
+// The difference from Mutex<T> is what happens while waiting. A Mutex<T> asks the OS to put the
+// thread to sleep until the lock is free. A spinlock just keeps trying in a loop, burning CPU the
+// whole time. That is only a win when the lock is held for a tiny amount of time, and it is a big
+// loss if the holder gets descheduled. It also has no poisoning, if a holder panics the guard is
+// still dropped and the lock is released.
+//This is the textbook version so it only uses std atomics, and swapping std::sync::atomic for
+// loom::sync::atomic is all it would take to model check the locking itself. That says nothing
+// about the guard though, whether it can be shared or sent is down to the marker impls below it.
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    strategy: SpinStrategy,
+    value: UnsafeCell<T>,
+}
+
+//What a thread does each time it finds the lock already taken. SpinLock is the only spin loop that
+// takes one. There is no spin barrier in this crate, and the `ready` wait in
+// producer_consumer_with_release_acquire() keeps a plain spin_loop() on purpose, it is there to
+// show the Release/Acquire pairing and waits for a single store that is already on its way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpinStrategy {
+    //Just spin_loop() and try again. Lowest latency, but it eats a whole core while waiting.
+    Busy,
+    //Give the rest of the time slice back to the OS with thread::yield_now(). This matters a lot
+    // when there are more threads than cores, since the lock holder might be waiting for a core.
+    Yield,
+    //Spin for an exponentially growing number of iterations and switch to yielding once that gets
+    // long. Contended threads back off so they hammer the flag less.
+    Backoff,
+}
+
+//Spinning 2^6 times is about where yielding starts to be cheaper than spinning.
+const MAX_BACKOFF_STEP: u32 = 6;
+
+fn spin_wait(strategy: SpinStrategy, step: &mut u32) {
+    match strategy {
+        SpinStrategy::Busy => hint::spin_loop(),
+        SpinStrategy::Yield => thread::yield_now(),
+        SpinStrategy::Backoff if *step <= MAX_BACKOFF_STEP => {
+            for _ in 0..1 << *step {
+                hint::spin_loop();
+            }
+            *step += 1;
+        }
+        SpinStrategy::Backoff => thread::yield_now(),
+    }
+}
+
+//UnsafeCell<T> is never Sync on its own, the compiler has no idea the flag protects it. This is
+// the same promise Mutex<T> makes, only one thread can ever get at the value at a time.
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub fn new(value: T) -> SpinLock<T> {
+        SpinLock::with_strategy(value, SpinStrategy::Busy)
+    }
+
+    pub fn with_strategy(value: T, strategy: SpinStrategy) -> SpinLock<T> {
+        SpinLock {
+            locked: AtomicBool::new(false),
+            strategy,
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        //Acquire on success pairs with the Release store in the guard's drop, so everything the
+        // last holder wrote is visible to the new holder.
+        let mut step = 0;
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            spin_wait(self.strategy, &mut step);
+        }
+
+        SpinLockGuard { lock: self, _not_send: PhantomData }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+    //With only the reference in here the guard would be Send and Sync whenever T is Send, because
+    // SpinLock<T> is. Then two threads could share one &SpinLockGuard<Cell<u64>> and both poke
+    // the Cell through Deref. A raw pointer is neither Send nor Sync, so this turns both off.
+    _not_send: PhantomData<*const ()>,
+}
+
+//Sharing &SpinLockGuard<T> only hands out &T, so that is fine exactly when &T can be shared. This
+// is the same rule MutexGuard<T> follows. It stays !Send, like MutexGuard, so the lock is always
+// released by the thread that took it.
+unsafe impl<T: Sync> Sync for SpinLockGuard<'_, T> {}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        //Safe because the guard only exists while this thread holds the lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+pub fn spinning_instead_of_sleeping() {
+    let counter = SpinLock::new(0);
+
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..10_000 {
+                    *counter.lock() += 1;
+                }
+            });
+        }
+    });
+
+    println!("spinlock counter: {}", counter.into_inner());
+}
+
+pub fn comparing_spin_strategies() {
+    for strategy in [SpinStrategy::Busy, SpinStrategy::Yield, SpinStrategy::Backoff] {
+        let counter = SpinLock::with_strategy(0, strategy);
+
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..5_000 {
+                        *counter.lock() += 1;
+                    }
+                });
+            }
+        });
+
+        println!("{:?} spinlock counter: {}", strategy, counter.into_inner());
+    }
+}
+
+//lock_all() prevents deadlocks by never locking out of order. TrackedMutex goes the other way and
+// notices a deadlock once it has actually happened. Every TrackedMutex reports to one global
+// wait-for graph: who holds each lock and which lock each blocked thread is waiting on. A thread
+// that has to block adds its edge and follows the chain, the holder of the lock it wants, the lock
+// that holder wants, and so on. If the chain comes back to itself nobody in it can ever move
+// again, so the deadlock handler gets called with the cycle. It only reports, the threads stay
+// stuck, because there is no safe way to take a lock away from a thread.
+pub struct TrackedMutex<T> {
+    id: usize,
+    name: &'static str,
+    inner: Mutex<T>,
+}
+
+//One edge of a deadlock, `thread` is blocked waiting for the lock called `lock`.
+#[derive(Debug, Clone)]
+pub struct WaitFor {
+    pub thread: ThreadId,
+    pub lock: &'static str,
+}
+
+type DeadlockHandler = Arc<dyn Fn(&[WaitFor]) + Send + Sync>;
+
+//A handful of Vecs instead of HashMaps so the whole thing can live in a plain static. There are
+// only ever as many entries as there are locked TrackedMutexes and blocked threads.
+struct WaitForGraph {
+    //(lock id, lock name, thread holding it)
+    holders: Vec<(usize, &'static str, ThreadId)>,
+    //(blocked thread, id of the lock it wants)
+    waiting: Vec<(ThreadId, usize)>,
+    handler: Option<DeadlockHandler>,
+}
+
+static WAIT_FOR_GRAPH: Mutex<WaitForGraph> = Mutex::new(WaitForGraph {
+    holders: Vec::new(),
+    waiting: Vec::new(),
+    handler: None,
+});
+static NEXT_LOCK_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn wait_for_graph() -> MutexGuard<'static, WaitForGraph> {
+    WAIT_FOR_GRAPH.lock().unwrap()
+}
+
+impl WaitForGraph {
+    fn holder(&self, lock: usize) -> Option<(&'static str, ThreadId)> {
+        self.holders
+            .iter()
+            .find(|(id, _, _)| *id == lock)
+            .map(|(_, name, thread)| (*name, *thread))
+    }
+
+    //Follows the chain starting at `me`. Every thread waits on at most one lock, so the walk is a
+    // straight line that either dead ends at a lock with no holder or a holder that isn't blocked,
+    // or comes back to `me`. A cycle that doesn't include `me` was already reported by whichever
+    // thread closed it, the step limit just stops the walk from going around it forever.
+    fn find_cycle(&self, me: ThreadId) -> Option<Vec<WaitFor>> {
+        let mut cycle = Vec::new();
+        let mut thread = me;
+
+        while cycle.len() <= self.waiting.len() {
+            let (_, lock) = *self.waiting.iter().find(|(waiter, _)| *waiter == thread)?;
+            let (lock, holder) = self.holder(lock)?;
+            cycle.push(WaitFor { thread, lock });
+            if holder == me {
+                return Some(cycle);
+            }
+            thread = holder;
+        }
+
+        None
+    }
+}
+
+//Replaces whatever handler was set before. It gets called on the thread that closed the cycle,
+// after the graph is unlocked, so it is free to use TrackedMutexes itself.
+pub fn on_deadlock<F>(handler: F)
+where
+    F: Fn(&[WaitFor]) + Send + Sync + 'static,
+{
+    wait_for_graph().handler = Some(Arc::new(handler));
+}
+
+impl<T> TrackedMutex<T> {
+    pub fn new(name: &'static str, value: T) -> TrackedMutex<T> {
+        TrackedMutex {
+            id: NEXT_LOCK_ID.fetch_add(1, Ordering::Relaxed),
+            name,
+            inner: Mutex::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> TrackedMutexGuard<'_, T> {
+        let me = thread::current().id();
+
+        //The uncontended case never touches the waiting list.
+        let guard = match self.inner.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                //If the holder lets go between the failed try_lock() and this, the edge just points
+                // at a lock with no holder and the walk stops there. Adding the edge and walking
+                // the chain happen under one lock of the graph, so of the threads in a cycle the
+                // last one to block always sees every edge.
+                let report = {
+                    let mut graph = wait_for_graph();
+                    graph.waiting.push((me, self.id));
+                    graph
+                        .find_cycle(me)
+                        .and_then(|cycle| graph.handler.clone().map(|handler| (handler, cycle)))
+                };
+                if let Some((handler, cycle)) = report {
+                    handler(&cycle);
+                }
+
+                let guard = self.inner.lock().unwrap();
+                wait_for_graph().waiting.retain(|(waiter, _)| *waiter != me);
+                guard
+            }
+        };
+
+        wait_for_graph().holders.push((self.id, self.name, me));
+        TrackedMutexGuard { lock: self, guard }
+    }
+}
+
+pub struct TrackedMutexGuard<'a, T> {
+    lock: &'a TrackedMutex<T>,
+    guard: MutexGuard<'a, T>,
+}
+
+impl<T> Deref for TrackedMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for TrackedMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+//The holder entry has to go before the real guard is dropped (fields drop after this runs).
+// Otherwise the next thread could lock it and add its own entry first, and this would remove it.
+impl<T> Drop for TrackedMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        wait_for_graph().holders.retain(|(id, _, _)| *id != self.lock.id);
+    }
+}
+
+pub fn detecting_a_real_deadlock() {
+    let (report_tx, report_rx) = mpsc::channel();
+    on_deadlock(move |cycle| {
+        let _ = report_tx.send(cycle.to_vec());
+    });
+
+    //An honest ABBA deadlock. The barrier makes sure each thread holds its first lock before
+    // either asks for its second, so it deadlocks every time instead of just sometimes.
+    let a = Arc::new(TrackedMutex::new("A", ()));
+    let b = Arc::new(TrackedMutex::new("B", ()));
+    let both_locked = Arc::new(Barrier::new(2));
+
+    for (first, second) in [(Arc::clone(&a), Arc::clone(&b)), (b, a)] {
+        let both_locked = Arc::clone(&both_locked);
+        thread::spawn(move || {
+            let _first = first.lock();
+            both_locked.wait();
+            let _second = second.lock();
+        });
+    }
+
+    //Those two threads can never be joined, so they are detached and left stuck until main()
+    // returns. The timeout is only there so a missed report can't hang the program too.
+    match report_rx.recv_timeout(Duration::from_secs(1)) {
+        Ok(cycle) => {
+            for edge in cycle {
+                println!("deadlock: {:?} is waiting for lock {}", edge.thread, edge.lock);
+            }
+        }
+        Err(_) => println!("no deadlock was reported"),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LockError {
+    //Somebody else held the lock for the whole timeout.
+    Timeout,
+    //A thread panicked while holding the lock (see pitfalls::poisoning_demo()).
+    Poisoned,
+}
+
+//std's Mutex has no lock-with-a-timeout, so this keeps calling try_lock() until the deadline,
+// sleeping a little in between so it doesn't eat a core the way a SpinLock would. The sleep is
+// capped at 1ms, that is the most it can overshoot the timeout or miss the lock being released.
+// A poisoned mutex is an error here. safe_lock_with() can recover the guard instead.
+pub fn safe_lock<T>(m: &Mutex<T>, timeout: Duration) -> Result<MutexGuard<'_, T>, LockError> {
+    safe_lock_with(m, timeout, false)
+}
+
+//With recover_poison set a poisoned mutex hands back its guard like PoisonError::into_inner()
+// would. The mutex stays poisoned, so every later lock() still gets to decide for itself.
+pub fn safe_lock_with<T>(
+    m: &Mutex<T>,
+    timeout: Duration,
+    recover_poison: bool,
+) -> Result<MutexGuard<'_, T>, LockError> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        match m.try_lock() {
+            Ok(guard) => return Ok(guard),
+            Err(TryLockError::Poisoned(poison)) if recover_poison => return Ok(poison.into_inner()),
+            Err(TryLockError::Poisoned(_)) => return Err(LockError::Poisoned),
+            Err(TryLockError::WouldBlock) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(LockError::Timeout);
+                }
+                thread::sleep(remaining.min(Duration::from_millis(1)));
+            }
+        }
+    }
+}
+
+pub fn locking_with_a_timeout() {
+    let shared = Arc::new(Mutex::new(5));
+    println!(
+        "free mutex: {:?}",
+        safe_lock(&shared, Duration::from_millis(20)).map(|value| *value)
+    );
+
+    //Hold the lock from another thread for much longer than the timeout.
+    let (locked_tx, locked_rx) = mpsc::channel();
+    thread::scope(|s| {
+        s.spawn(|| {
+            let _held = shared.lock().unwrap();
+            locked_tx.send(()).unwrap();
+            thread::sleep(Duration::from_millis(200));
+        });
+        locked_rx.recv().unwrap();
+        println!(
+            "held mutex: {:?}",
+            safe_lock(&shared, Duration::from_millis(20)).map(|value| *value)
+        );
+    });
+
+    let panicking = {
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            let mut value = shared.lock().unwrap();
+            *value += 1;
+            panic!("panicked while holding the lock");
+        })
+    };
+    assert!(panicking.join().is_err(), "The thread was supposed to panic");
+
+    println!(
+        "poisoned mutex: {:?}",
+        safe_lock(&shared, Duration::from_millis(20)).map(|value| *value)
+    );
+    println!(
+        "poisoned mutex, recovering: {:?}",
+        safe_lock_with(&shared, Duration::from_millis(20), true).map(|value| *value)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn lock_all_in_opposite_orders_does_not_deadlock() {
+        let checking = Mutex::new(1000);
+        let savings = Mutex::new(1000);
+
+        //If lock_all() deadlocked the scope would never end, so run it on a helper thread and
+        // give up after a generous timeout instead of hanging the test run.
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::spawn(move || {
+            thread::scope(|s| {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        let mut accounts = lock_all(&[&checking, &savings]);
+                        *accounts[0] -= 1;
+                        *accounts[1] += 1;
+                    }
+                });
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        let mut accounts = lock_all(&[&savings, &checking]);
+                        *accounts[0] -= 2;
+                        *accounts[1] += 2;
+                    }
+                });
+            });
+            let _ = done_tx.send((checking.into_inner().unwrap(), savings.into_inner().unwrap()));
+        });
+
+        let balances = done_rx.recv_timeout(Duration::from_secs(10));
+        assert_eq!(balances, Ok((2000, 0)));
+    }
+
+    #[test]
+    fn lock_all_returns_guards_in_input_order() {
+        let a = Mutex::new('a');
+        let b = Mutex::new('b');
+        let c = Mutex::new('c');
+
+        let guards = lock_all(&[&c, &a, &b]);
+        let values: Vec<char> = guards.iter().map(|guard| **guard).collect();
+        assert_eq!(values, vec!['c', 'a', 'b']);
+    }
+
+    #[test]
+    fn spinlock_counter_is_exact_under_contention() {
+        let counter = SpinLock::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        *counter.lock() += 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(counter.into_inner(), 40_000);
+    }
+
+    #[test]
+    fn every_spin_strategy_keeps_the_count_exact() {
+        for strategy in [SpinStrategy::Busy, SpinStrategy::Yield, SpinStrategy::Backoff] {
+            let counter = SpinLock::with_strategy(0, strategy);
+
+            thread::scope(|s| {
+                for _ in 0..8 {
+                    s.spawn(|| {
+                        for _ in 0..2_000 {
+                            *counter.lock() += 1;
+                        }
+                    });
+                }
+            });
+
+            assert_eq!(counter.into_inner(), 16_000, "{strategy:?}");
+        }
+    }
+
+    #[test]
+    fn every_philosopher_eats_every_round() {
+        for n in [2, 5, 8] {
+            assert_eq!(dining_philosophers(n, 200), vec![200; n]);
+        }
+    }
+
+    #[test]
+    fn abba_deadlock_is_reported_with_both_locks_and_threads() {
+        //The handler is global, this is the only test that sets one.
+        let (report_tx, report_rx) = mpsc::channel();
+        on_deadlock(move |cycle| {
+            let _ = report_tx.send(cycle.to_vec());
+        });
+
+        let a = Arc::new(TrackedMutex::new("A", ()));
+        let b = Arc::new(TrackedMutex::new("B", ()));
+        let both_locked = Arc::new(Barrier::new(2));
+        let (id_tx, id_rx) = mpsc::channel();
+
+        //The two threads stay stuck for the rest of the test run, there is no way to free them.
+        for (first, second) in [(Arc::clone(&a), Arc::clone(&b)), (b, a)] {
+            let (both_locked, id_tx) = (Arc::clone(&both_locked), id_tx.clone());
+            thread::spawn(move || {
+                id_tx.send(thread::current().id()).unwrap();
+                let _first = first.lock();
+                both_locked.wait();
+                let _second = second.lock();
+            });
+        }
+
+        let cycle = report_rx.recv_timeout(Duration::from_secs(10)).expect("No deadlock reported");
+        let mut threads = vec![id_rx.recv().unwrap(), id_rx.recv().unwrap()];
+        assert_eq!(cycle.len(), 2, "{cycle:?}");
+
+        let mut locks: Vec<&str> = cycle.iter().map(|edge| edge.lock).collect();
+        locks.sort();
+        assert_eq!(locks, ["A", "B"]);
+
+        let mut waiting: Vec<ThreadId> = cycle.iter().map(|edge| edge.thread).collect();
+        let key = |id: &ThreadId| format!("{id:?}");
+        waiting.sort_by_key(key);
+        threads.sort_by_key(key);
+        assert_eq!(waiting, threads);
+    }
+
+    #[test]
+    fn safe_lock_times_out_on_a_held_lock() {
+        let shared = Mutex::new(5);
+        assert_eq!(*safe_lock(&shared, Duration::from_millis(20)).unwrap(), 5);
+
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        thread::scope(|s| {
+            let shared = &shared;
+            s.spawn(move || {
+                let _held = shared.lock().unwrap();
+                locked_tx.send(()).unwrap();
+                let _ = release_rx.recv();
+            });
+            locked_rx.recv().unwrap();
+
+            let started = Instant::now();
+            let result = safe_lock(shared, Duration::from_millis(30)).map(|value| *value);
+            assert_eq!(result, Err(LockError::Timeout));
+            assert!(started.elapsed() >= Duration::from_millis(30));
+            drop(release_tx);
+        });
+
+        //Free again once the holder is gone.
+        assert_eq!(*safe_lock(&shared, Duration::from_millis(20)).unwrap(), 5);
+    }
+
+    #[test]
+    fn safe_lock_reports_poison_and_safe_lock_with_recovers() {
+        let shared = Arc::new(Mutex::new(5));
+        let panicking = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                let mut value = shared.lock().unwrap();
+                *value += 1;
+                panic!("panicked while holding the lock");
+            })
+        };
+        assert!(panicking.join().is_err());
+
+        let poisoned = safe_lock(&shared, Duration::from_millis(20)).map(|value| *value);
+        assert_eq!(poisoned, Err(LockError::Poisoned));
+        let strict = safe_lock_with(&shared, Duration::from_millis(20), false).map(|value| *value);
+        assert_eq!(strict, Err(LockError::Poisoned));
+        let recovered = safe_lock_with(&shared, Duration::from_millis(20), true).map(|v| *v);
+        assert_eq!(recovered, Ok(6));
+        assert!(shared.is_poisoned());
+    }
+
+    //Stable Rust can't write "T is not Sync" as a bound, so this leans on method lookup instead.
+    // The inherent const only applies when the bound holds, otherwise the trait's false is used.
+    struct Probe<T>(PhantomData<T>);
+
+    trait Fallback {
+        const IS_SYNC: bool = false;
+        const IS_SEND: bool = false;
+    }
+
+    impl<T> Fallback for Probe<T> {}
+
+    struct SendProbe<T>(PhantomData<T>);
+
+    impl<T> Fallback for SendProbe<T> {}
+
+    impl<T: Sync> Probe<T> {
+        const IS_SYNC: bool = true;
+    }
+
+    impl<T: Send> SendProbe<T> {
+        const IS_SEND: bool = true;
+    }
+
+    #[test]
+    fn spinlock_guard_is_only_sync_when_the_value_is() {
+        let guard_of_cell = (
+            Probe::<SpinLockGuard<'static, Cell<u64>>>::IS_SYNC,
+            SendProbe::<SpinLockGuard<'static, Cell<u64>>>::IS_SEND,
+        );
+        let guard_of_u64 = (
+            Probe::<SpinLockGuard<'static, u64>>::IS_SYNC,
+            SendProbe::<SpinLockGuard<'static, u64>>::IS_SEND,
+        );
+        //The lock itself is still shareable and sendable for any Send value, Cell<u64> included.
+        let lock_of_cell = (
+            Probe::<SpinLock<Cell<u64>>>::IS_SYNC,
+            SendProbe::<SpinLock<Cell<u64>>>::IS_SEND,
+        );
+
+        assert_eq!(guard_of_cell, (false, false));
+        assert_eq!(guard_of_u64, (true, false));
+        assert_eq!(lock_of_cell, (true, true));
+    }
+}
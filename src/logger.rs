@@ -0,0 +1,338 @@
+use std::io::{self, Write};
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+//A logger that hands messages off to a dedicated thread over a bounded channel. If the logging
+// thread can't keep up and the buffer fills, log() throws the message away instead of blocking
+// the caller. Losing some log lines is usually better than slowing down the real work.
+pub struct ThrottledLogger {
+    sender: Option<mpsc::SyncSender<String>>,
+    thread: Option<thread::JoinHandle<()>>,
+    dropped: AtomicUsize,
+}
+
+impl ThrottledLogger {
+    pub fn new(capacity: usize) -> ThrottledLogger {
+        ThrottledLogger::with_sink(capacity, |message| println!("[log] {message}"))
+    }
+
+    //Same as new(), but every message that makes it through goes to `sink` instead of stdout.
+    pub fn with_sink<F>(capacity: usize, mut sink: F) -> ThrottledLogger
+    where
+        F: FnMut(String) + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel::<String>(capacity);
+
+        let thread = thread::spawn(move || {
+            for message in receiver {
+                sink(message);
+            }
+        });
+
+        ThrottledLogger {
+            sender: Some(sender),
+            thread: Some(thread),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    //try_send() is the non-blocking version of send() on a SyncSender. It fails with Full when
+    // the buffer has no room left.
+    pub fn log(&self, message: &str) {
+        let sender = self.sender.as_ref().expect("Sender only goes away on drop");
+
+        if let Err(mpsc::TrySendError::Full(_)) = sender.try_send(message.to_string()) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn dropped_count(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for ThrottledLogger {
+    fn drop(&mut self) {
+        //Anything still buffered gets written before the logging thread exits.
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("Logger thread crashed");
+        }
+    }
+}
+
+pub fn dropping_log_messages_under_load() {
+    let logger = ThrottledLogger::new(2);
+
+    for i in 0..200 {
+        logger.log(&format!("message {i}"));
+    }
+
+    println!("throttled logger dropped {} of 200 messages", logger.dropped_count());
+}
+
+//Producers append to a shared in-memory buffer and one flusher thread does all of the actual
+// writing, so lots of small records turn into a few big writes and no producer ever waits on
+// I/O. The flusher wakes up every `interval`, or straight away once the buffer grows past
+// `threshold`. It swaps the buffer out for an empty one and writes it with the lock released, so
+// producers can keep appending while a write is in progress.
+pub struct BufferedWriter<W: Write + Send + 'static> {
+    shared: Arc<WriteBuffer>,
+    threshold: usize,
+    flusher: Option<thread::JoinHandle<io::Result<W>>>,
+}
+
+struct WriteBuffer {
+    state: Mutex<WriteBufferState>,
+    wake: Condvar,
+}
+
+struct WriteBufferState {
+    pending: Vec<u8>,
+    closed: bool,
+    //Set by the flusher when the sink fails. Nothing written after that would ever reach the sink.
+    failed: bool,
+}
+
+impl<W: Write + Send + 'static> BufferedWriter<W> {
+    pub fn new(mut sink: W, interval: Duration, threshold: usize) -> BufferedWriter<W> {
+        let shared = Arc::new(WriteBuffer {
+            state: Mutex::new(WriteBufferState {
+                pending: Vec::new(),
+                closed: false,
+                failed: false,
+            }),
+            wake: Condvar::new(),
+        });
+
+        let flusher = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || loop {
+                let state = shared.state.lock().unwrap();
+                let (mut state, _) = shared
+                    .wake
+                    .wait_timeout_while(state, interval, |state| {
+                        state.pending.len() < threshold && !state.closed
+                    })
+                    .unwrap();
+                let batch = mem::take(&mut state.pending);
+                let closed = state.closed;
+                drop(state);
+
+                if !batch.is_empty() {
+                    if let Err(error) = sink.write_all(&batch).and_then(|_| sink.flush()) {
+                        let mut state = shared.state.lock().unwrap();
+                        state.failed = true;
+                        state.pending = Vec::new();
+                        return Err(error);
+                    }
+                }
+                //Nothing can be appended after close, so this batch was the last one.
+                if closed {
+                    return Ok(sink);
+                }
+            })
+        };
+
+        BufferedWriter {
+            shared,
+            threshold,
+            flusher: Some(flusher),
+        }
+    }
+
+    //Fails once the flusher has stopped on a write error, instead of quietly buffering records
+    // that nobody is going to write. The error itself comes back from finish().
+    pub fn write_record(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.failed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the flusher stopped after a write error",
+            ));
+        }
+        state.pending.extend_from_slice(bytes);
+        if state.pending.len() >= self.threshold {
+            self.shared.wake.notify_one();
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Option<io::Result<W>> {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.wake.notify_one();
+
+        let flusher = self.flusher.take()?;
+        Some(flusher.join().expect("Flusher thread crashed"))
+    }
+
+    //Flushes whatever is still buffered and hands the sink back. A write error stops the flusher,
+    // and this is where the original error shows up.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.close().expect("The flusher is only taken by finish() or drop")
+    }
+}
+
+impl<W: Write + Send + 'static> Drop for BufferedWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+//A Vec<u8> that can still be looked at while the flusher owns it.
+#[derive(Clone, Default)]
+struct SharedSink(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedSink {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn batching_writes_on_a_flusher_thread() {
+    let sink = SharedSink::default();
+    let writer = BufferedWriter::new(sink.clone(), Duration::from_millis(20), 1024);
+
+    thread::scope(|s| {
+        for producer in 0..3 {
+            let writer = &writer;
+            s.spawn(move || {
+                for record in 0..3 {
+                    let line = format!("producer {producer} record {record}\n");
+                    writer.write_record(line.as_bytes()).expect("The shared sink can't fail");
+                }
+            });
+        }
+    });
+
+    println!("bytes in the sink right after writing: {}", sink.0.lock().unwrap().len());
+    thread::sleep(Duration::from_millis(50));
+    let flushed = String::from_utf8(sink.0.lock().unwrap().clone()).expect("Records are UTF-8");
+    println!("records in the sink after the flusher ran: {}", flushed.lines().count());
+
+    //A plain Vec<u8> works too, finish() hands it back with everything written to it.
+    let writer = BufferedWriter::new(Vec::new(), Duration::from_secs(60), 8);
+    writer.write_record(b"more than eight bytes").expect("Writing to a Vec<u8> can't fail");
+    writer.write_record(b", and the rest").expect("Writing to a Vec<u8> can't fail");
+    let written = writer.finish().expect("Writing to a Vec<u8> can't fail");
+    println!("Vec<u8> sink after finish(): {:?}", String::from_utf8_lossy(&written));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn every_message_is_either_delivered_or_dropped() {
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        //The sink gets stuck on the first message, so the tiny buffer is guaranteed to fill up.
+        let logger = {
+            let delivered = Arc::clone(&delivered);
+            ThrottledLogger::with_sink(2, move |message| {
+                let _ = release_rx.recv();
+                delivered.lock().unwrap().push(message);
+            })
+        };
+
+        for i in 0..200 {
+            logger.log(&format!("message {i}"));
+        }
+        let dropped = logger.dropped_count();
+
+        drop(release_tx);
+        drop(logger);
+
+        let delivered = delivered.lock().unwrap().len();
+        assert!(dropped > 0);
+        assert!(delivered > 0);
+        assert_eq!(dropped + delivered, 200);
+    }
+
+    #[test]
+    fn every_record_reaches_the_sink() {
+        let sink = SharedSink::default();
+        let writer = BufferedWriter::new(sink.clone(), Duration::from_millis(10), 1 << 20);
+        thread::scope(|s| {
+            for producer in 0..3 {
+                let writer = &writer;
+                s.spawn(move || {
+                    for record in 0..50 {
+                        let line = format!("producer {producer} record {record}\n");
+                        writer.write_record(line.as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+
+        //Way under the threshold, so only the interval gets these written.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while sink.0.lock().unwrap().iter().filter(|&&byte| byte == b'\n').count() < 150 {
+            assert!(Instant::now() < deadline, "The flusher never wrote everything");
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let flushed = String::from_utf8(sink.0.lock().unwrap().clone()).unwrap();
+        for producer in 0..3 {
+            let prefix = format!("producer {producer} ");
+            let records: Vec<&str> =
+                flushed.lines().filter(|line| line.starts_with(&prefix)).collect();
+            let expected: Vec<String> =
+                (0..50).map(|record| format!("producer {producer} record {record}")).collect();
+            assert_eq!(records, expected);
+        }
+        drop(writer);
+    }
+
+    #[test]
+    fn finish_writes_what_is_left_to_a_vec() {
+        let writer = BufferedWriter::new(Vec::new(), Duration::from_secs(60), 1 << 20);
+        writer.write_record(b"first,").unwrap();
+        writer.write_record(b"second").unwrap();
+        assert_eq!(writer.finish().unwrap(), b"first,second");
+    }
+
+    struct FailingSink;
+
+    impl Write for FailingSink {
+        fn write(&mut self, _bytes: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_failing_sink_stops_write_record() {
+        let writer = BufferedWriter::new(FailingSink, Duration::from_millis(5), 4);
+        writer.write_record(b"past the threshold").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let error = loop {
+            match writer.write_record(b"more") {
+                Ok(()) => assert!(Instant::now() < deadline, "write_record kept succeeding"),
+                Err(error) => break error,
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+
+        match writer.finish() {
+            Err(error) => assert_eq!(error.to_string(), "disk full"),
+            Ok(_) => panic!("finish() should pass on the write error"),
+        }
+    }
+}
@@ -1,8 +1,29 @@
-use std::ops::Add;
 use std::sync::{Arc, mpsc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+mod actor;
+mod bench;
+mod broadcast;
+mod channel_adapters;
+mod channels;
+mod compile_tests;
+mod concurrent_collections;
+mod locks;
+mod logger;
+mod monitoring;
+mod parallel;
+mod parallel_algorithms;
+mod patterns;
+mod pitfalls;
+mod priority_pool;
+mod rng;
+mod scheduler;
+mod shutdown;
+mod supervisor;
+mod sync_primitives;
+mod thread_pool;
+
 fn main() {
     //I tend to use concurrent programming and parallel programming interchangeably. However,
     // apparently they have slightly different meanings. Concurrent programming simply means that
@@ -15,6 +36,102 @@ fn main() {
     using_message_passing_to_transfer_data_between_threads();
     shared_state_concurrency();
     extensibility_concurrency_with_the_sync_and_send_traits();
+
+    compile_tests::data_races_the_compiler_prevents();
+    thread_pool::submitting_jobs_with_handles();
+    thread_pool::draining_a_pool_before_shutdown();
+    thread_pool::timing_out_on_a_full_pool();
+    thread_pool::resizing_a_pool();
+    scheduler::cooperative_scheduling_on_a_pool();
+    scheduler::running_tasks_with_dependencies();
+    priority_pool::running_high_priority_jobs_first();
+    priority_pool::aging_low_priority_items();
+    concurrent_collections::registering_handlers_from_multiple_threads();
+    concurrent_collections::counting_events_by_key();
+    concurrent_collections::reusing_pooled_resources();
+    concurrent_collections::blocking_stack_push_and_pop();
+    concurrent_collections::recording_into_an_atomic_histogram();
+    concurrent_collections::racing_compare_and_set();
+    concurrent_collections::claiming_indices_with_a_bitset();
+    concurrent_collections::generating_unique_ids();
+    channels::preserving_order_per_producer();
+    channels::measuring_message_latency();
+    channels::building_a_barrier_from_channels();
+    channels::moving_owned_data_through_channels();
+    channels::ending_the_loop_when_senders_drop();
+    channels::merging_sorted_streams();
+    channels::summing_values_per_time_window();
+    channels::timing_out_blocking_work();
+    channels::acknowledging_messages();
+    channels::resolving_promises();
+    channels::averaging_a_stream();
+    channels::splitting_off_errors();
+    channels::peeking_at_the_next_message();
+    channels::lock_step_flow_control();
+    channel_adapters::transforming_receivers();
+    parallel::map_reduce_histogram();
+    parallel::parallel_any_and_all();
+    parallel::finding_in_parallel();
+    parallel::grouping_in_parallel();
+    parallel::mapping_with_adaptive_chunks();
+    parallel::mapping_thread_ids_to_results();
+    parallel::collecting_spawned_results();
+    parallel::cancelling_a_parallel_map();
+    parallel::handling_failures_in_parallel_map();
+    parallel::throttling_concurrent_work();
+    locks::locking_in_a_consistent_order();
+    locks::philosophers_without_deadlock();
+    locks::spinning_instead_of_sleeping();
+    locks::comparing_spin_strategies();
+    locks::detecting_a_real_deadlock();
+    locks::locking_with_a_timeout();
+    logger::dropping_log_messages_under_load();
+    logger::batching_writes_on_a_flusher_thread();
+    sync_primitives::limiting_concurrency_with_a_gate();
+    sync_primitives::granting_permits_in_arrival_order();
+    sync_primitives::barrier_with_a_timeout();
+    sync_primitives::staged_startup();
+    sync_primitives::waiting_on_a_wait_group_with_a_timeout();
+    sync_primitives::counting_down_a_latch();
+    parallel_algorithms::parallel_dot_product();
+    parallel_algorithms::translating_with_a_shared_table();
+    parallel_algorithms::finding_the_nearest_point();
+    parallel_algorithms::parallel_stencil_with_barriers();
+    parallel_algorithms::parsing_rows_in_parallel();
+    parallel_algorithms::reducing_with_gcd();
+    parallel_algorithms::counting_radix_buckets();
+    parallel_algorithms::sorting_in_parallel();
+    parallel_algorithms::intersecting_in_parallel();
+    parallel_algorithms::finding_min_and_max();
+    parallel_algorithms::processing_lines_in_parallel();
+    parallel_algorithms::grepping_in_parallel();
+    parallel_algorithms::scanning_prefix_sums();
+    parallel_algorithms::deduplicating_in_parallel();
+    broadcast::detecting_lagging_subscribers();
+    broadcast::ticking_subscribers_in_sync();
+    actor::flooding_a_bounded_mailbox();
+    pitfalls::holding_a_lock_too_long();
+    pitfalls::interleaving_output();
+    pitfalls::nesting_arc_and_mutex();
+    pitfalls::channel_capacity_and_deadlock();
+    pitfalls::poisoning_a_mutex();
+    pitfalls::starving_a_polite_thread();
+    pitfalls::locking_a_mutex_twice();
+    patterns::swapping_double_buffers();
+    patterns::publishing_with_release_and_acquire();
+    rng::reproducible_parallel_randomness();
+    rng::estimating_pi();
+    bench::benchmarking_channels();
+    bench::benchmarking_atomic_orderings();
+    shutdown::shutting_down_on_ctrl_c();
+    shutdown::keeping_partial_results_after_cancel();
+    monitoring::monitoring_worker_heartbeats();
+    monitoring::detecting_stale_workers();
+    monitoring::reporting_progress();
+    supervisor::restarting_panicked_workers();
+    supervisor::polling_for_completion();
+    supervisor::joining_threads_on_drop();
+    supervisor::catching_unjoined_threads();
 }
 
 fn using_threads_to_run_code_simultaneously() {
@@ -107,8 +224,9 @@ fn shared_state_concurrency() {
     // than I am used to.
     let m = Mutex::new(5);
     {
-        //This will panic if the lock is already held by this thread. Otherwise it will block until
-        // it can acquire the lock.
+        //This blocks until it can acquire the lock. If this thread already holds the lock it never
+        // returns, the docs say it might panic or deadlock. On Linux it deadlocks, see
+        // pitfalls::double_lock_demo().
         //This returns a MutexGuard which is a smart pointer and represents the lock. When it goes
         // out of scope, the mutex will unlock.
         let mut value = m.lock().unwrap();
@@ -128,7 +246,7 @@ fn shared_state_concurrency() {
         threads.push(
             thread::spawn(move || {
                 let mut string = next_mutex.lock().unwrap();
-                string.push(('a' as u8 + i) as char);
+                string.push((b'a' + i) as char);
             })
         );
     }
@@ -0,0 +1,344 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//Workers call beat() every so often to prove they are still making progress. A separate monitor
+// thread wakes up regularly and records every worker that hasn't beaten within `timeout` as
+// stalled. A worker that starts beating again drops off the stalled list on the next check.
+pub struct HeartbeatMonitor {
+    last_beats: Arc<Mutex<Vec<Instant>>>,
+    stalled: Arc<Mutex<Vec<usize>>>,
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl HeartbeatMonitor {
+    pub fn new(workers: usize, timeout: Duration) -> HeartbeatMonitor {
+        let last_beats = Arc::new(Mutex::new(vec![Instant::now(); workers]));
+        let stalled = Arc::new(Mutex::new(Vec::new()));
+        let (stop, stop_rx) = mpsc::channel::<()>();
+
+        let thread = {
+            let last_beats = Arc::clone(&last_beats);
+            let stalled = Arc::clone(&stalled);
+            thread::spawn(move || {
+                //recv_timeout() doubles as an interruptible sleep. Nothing is ever sent on the
+                // stop channel, it disconnects when the monitor is dropped and that ends the loop.
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stop_rx.recv_timeout(timeout / 4) {
+                    let now_stalled: Vec<usize> = last_beats
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, last_beat)| last_beat.elapsed() > timeout)
+                        .map(|(id, _)| id)
+                        .collect();
+
+                    *stalled.lock().unwrap() = now_stalled;
+                }
+            })
+        };
+
+        HeartbeatMonitor {
+            last_beats,
+            stalled,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn beat(&self, worker_id: usize) {
+        self.last_beats.lock().unwrap()[worker_id] = Instant::now();
+    }
+
+    pub fn stalled(&self) -> Vec<usize> {
+        self.stalled.lock().unwrap().clone()
+    }
+}
+
+impl Drop for HeartbeatMonitor {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("Heartbeat monitor crashed");
+        }
+    }
+}
+
+pub fn monitoring_worker_heartbeats() {
+    let monitor = Arc::new(HeartbeatMonitor::new(2, Duration::from_millis(50)));
+
+    //Worker 0 keeps beating the whole time. Worker 1 beats a couple of times and then gets stuck.
+    let workers: Vec<_> = [20, 2]
+        .into_iter()
+        .enumerate()
+        .map(|(id, beats)| {
+            let monitor = Arc::clone(&monitor);
+            thread::spawn(move || {
+                for _ in 0..beats {
+                    monitor.beat(id);
+                    thread::sleep(Duration::from_millis(10));
+                }
+            })
+        })
+        .collect();
+
+    thread::sleep(Duration::from_millis(150));
+    println!("stalled workers: {:?}", monitor.stalled());
+
+    for worker in workers {
+        worker.join().expect("Heartbeat worker crashed");
+    }
+}
+
+//The message passing version of HeartbeatMonitor. Workers don't touch any shared state, they
+// send their id down a channel, and only the monitor thread knows when each worker was last
+// heard from. Instead of being polled for a list, the monitor calls `on_stale` on its own thread
+// the moment a worker goes quiet for longer than `timeout`. That happens once per silence, a
+// worker that starts beating again can be reported again if it goes quiet a second time.
+pub struct LivenessMonitor {
+    heartbeats: mpsc::Sender<usize>,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl LivenessMonitor {
+    pub fn new<F>(workers: usize, timeout: Duration, mut on_stale: F) -> LivenessMonitor
+    where
+        F: FnMut(usize) + Send + 'static,
+    {
+        let (heartbeats, heartbeat_rx) = mpsc::channel::<usize>();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let mut last_heard = vec![Instant::now(); workers];
+                let mut reported = vec![false; workers];
+
+                //The monitor holds on to a sender itself, so the channel never disconnects and
+                // the stop flag is what ends the loop.
+                while !stop.load(Ordering::SeqCst) {
+                    if let Ok(id) = heartbeat_rx.recv_timeout(timeout / 4) {
+                        last_heard[id] = Instant::now();
+                        reported[id] = false;
+                    }
+
+                    for id in 0..workers {
+                        if !reported[id] && last_heard[id].elapsed() > timeout {
+                            reported[id] = true;
+                            on_stale(id);
+                        }
+                    }
+                }
+            })
+        };
+
+        LivenessMonitor {
+            heartbeats,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    //Every worker gets its own sender and sends its id on it as a heartbeat.
+    pub fn heartbeat_sender(&self) -> mpsc::Sender<usize> {
+        self.heartbeats.clone()
+    }
+}
+
+impl Drop for LivenessMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("Liveness monitor crashed");
+        }
+    }
+}
+
+pub fn detecting_stale_workers() {
+    let stale = Arc::new(Mutex::new(Vec::new()));
+    let monitor = {
+        let stale = Arc::clone(&stale);
+        LivenessMonitor::new(3, Duration::from_millis(50), move |id| {
+            stale.lock().unwrap().push(id);
+        })
+    };
+
+    //Worker 1 sends two heartbeats and then goes quiet while the other two keep going.
+    let workers: Vec<_> = [20, 2, 20]
+        .into_iter()
+        .enumerate()
+        .map(|(id, beats)| {
+            let heartbeat = monitor.heartbeat_sender();
+            thread::spawn(move || {
+                for _ in 0..beats {
+                    heartbeat.send(id).expect("Monitor hung up");
+                    thread::sleep(Duration::from_millis(10));
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.join().expect("Heartbeat worker crashed");
+    }
+    drop(monitor);
+
+    println!("workers reported as stale: {:?}", *stale.lock().unwrap());
+}
+
+//A shared counter that workers bump as they finish pieces of work. Cloning it clones the Arc, so
+// every clone counts into the same total. Relaxed ordering is fine since nothing else is being
+// synchronized through the counter, a reading that is a moment out of date is good enough for a
+// progress bar.
+#[derive(Clone, Default)]
+pub struct Progress {
+    done: Arc<AtomicUsize>,
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        Progress::default()
+    }
+
+    pub fn inc(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn fraction(&self, total: usize) -> f64 {
+        if total == 0 {
+            return 1.0;
+        }
+        self.done.load(Ordering::Relaxed) as f64 / total as f64
+    }
+}
+
+//Splits `total` units of work across the workers and samples the progress from the main thread
+// while they run. Returns every sample that was taken, the last one always being after all the
+// workers have been joined.
+pub fn run_with_progress(total: usize, threads: usize) -> Vec<f64> {
+    let progress = Progress::new();
+    let threads = threads.max(1);
+
+    let workers: Vec<_> = (0..threads)
+        .map(|worker| {
+            let progress = progress.clone();
+            //Worker n takes units n, n + threads, n + 2 * threads, ...
+            let units = (worker..total).step_by(threads).count();
+            thread::spawn(move || {
+                for _ in 0..units {
+                    thread::sleep(Duration::from_millis(2));
+                    progress.inc();
+                }
+            })
+        })
+        .collect();
+
+    let mut samples = Vec::new();
+    while workers.iter().any(|worker| !worker.is_finished()) {
+        samples.push(progress.fraction(total));
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    for worker in workers {
+        worker.join().expect("Progress worker crashed");
+    }
+    samples.push(progress.fraction(total));
+
+    samples
+}
+
+pub fn reporting_progress() {
+    let samples = run_with_progress(100, 4);
+    let formatted: Vec<String> = samples.iter().map(|sample| format!("{:.2}", sample)).collect();
+    println!("progress samples: {}", formatted.join(" "));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silent_worker_is_flagged_as_stalled() {
+        let monitor = HeartbeatMonitor::new(2, Duration::from_millis(50));
+
+        //Worker 0 keeps beating, worker 1 never does after the monitor starts.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut stalled = Vec::new();
+        while Instant::now() < deadline {
+            monitor.beat(0);
+            stalled = monitor.stalled();
+            if !stalled.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(stalled, vec![1]);
+
+        //Once worker 1 beats again it drops off the list on a later check.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !monitor.stalled().is_empty() && Instant::now() < deadline {
+            monitor.beat(0);
+            monitor.beat(1);
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(monitor.stalled().is_empty());
+    }
+
+    #[test]
+    fn progress_only_goes_up_and_ends_at_the_total() {
+        for (total, threads) in [(100, 4), (7, 3), (1, 1)] {
+            let samples = run_with_progress(total, threads);
+
+            assert!(samples.windows(2).all(|pair| pair[0] <= pair[1]), "{samples:?}");
+            assert!(samples.iter().all(|sample| (0.0..=1.0).contains(sample)), "{samples:?}");
+            assert_eq!(samples.last(), Some(&1.0));
+        }
+        assert_eq!(Progress::new().fraction(0), 1.0);
+    }
+
+    #[test]
+    fn only_the_silent_worker_is_reported_stale() {
+        let stale = Arc::new(Mutex::new(Vec::new()));
+        let monitor = {
+            let stale = Arc::clone(&stale);
+            LivenessMonitor::new(3, Duration::from_millis(300), move |id| {
+                stale.lock().unwrap().push(id);
+            })
+        };
+
+        //Workers 0 and 2 beat every 10ms until told to stop, worker 1 never beats at all.
+        let beating = Arc::new(AtomicBool::new(true));
+        let workers: Vec<_> = [0, 2]
+            .into_iter()
+            .map(|id| {
+                let (heartbeat, beating) = (monitor.heartbeat_sender(), Arc::clone(&beating));
+                thread::spawn(move || {
+                    while beating.load(Ordering::SeqCst) {
+                        heartbeat.send(id).unwrap();
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                })
+            })
+            .collect();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while stale.lock().unwrap().is_empty() {
+            assert!(Instant::now() < deadline, "The silent worker was never reported");
+            thread::sleep(Duration::from_millis(5));
+        }
+        //Some more time for a wrong report about the healthy workers to show up.
+        thread::sleep(Duration::from_millis(100));
+
+        beating.store(false, Ordering::SeqCst);
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        drop(monitor);
+
+        //Reported once only, it isn't reported again on every check.
+        assert_eq!(*stale.lock().unwrap(), vec![1]);
+    }
+}
@@ -0,0 +1,1116 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::shutdown::{CancellationToken, ShutdownTrigger};
+use crate::sync_primitives::Semaphore;
+
+//Splits the items into at most `parts` owned chunks of nearly equal size so that each chunk can
+// be moved onto its own thread.
+pub fn split_into_chunks<T>(items: Vec<T>, parts: usize) -> Vec<Vec<T>> {
+    let parts = parts.max(1);
+    let chunk_size = items.len().div_ceil(parts).max(1);
+
+    let mut chunks = Vec::with_capacity(parts);
+    let mut items = items.into_iter().peekable();
+    while items.peek().is_some() {
+        chunks.push(items.by_ref().take(chunk_size).collect());
+    }
+
+    chunks
+}
+
+//The map step runs in parallel, but every mapped value is sent back over a channel and reduced
+// on the calling thread as it arrives. That means reduce never runs on two threads at once, so
+// it doesn't need to be Sync (or even Send) and can freely mutate whatever it captured. A panic
+// in any of the map calls is passed on to the caller.
+pub fn map_reduce<T, M, R, F, G>(items: Vec<T>, workers: usize, map: F, reduce: G, init: R) -> R
+where
+    T: Send,
+    M: Send,
+    F: Fn(T) -> M + Sync,
+    G: FnMut(R, M) -> R,
+{
+    let policy = FailurePolicy::PanicOnFirst;
+    unwrap_panic_on_first(map_reduce_with_policy(items, workers, policy, map, reduce, init))
+}
+
+//map_reduce() with the choice of what a panicking map call does. With ContinueIgnore its item
+// simply never gets reduced.
+pub fn map_reduce_with_policy<T, M, R, F, G>(
+    items: Vec<T>,
+    workers: usize,
+    policy: FailurePolicy,
+    map: F,
+    mut reduce: G,
+    init: R,
+) -> Result<R, Vec<JobFailure>>
+where
+    T: Send,
+    M: Send,
+    F: Fn(T) -> M + Sync,
+    G: FnMut(R, M) -> R,
+{
+    let (tx, rx) = mpsc::channel();
+    let failed = AtomicBool::new(false);
+    let mut start = 0;
+
+    //thread::scope lets the workers borrow `map` instead of needing it to be 'static. All of the
+    // scoped threads are joined before scope() returns.
+    let (reduced, failures) = thread::scope(|s| {
+        for chunk in split_into_chunks(items, workers) {
+            let offset = start;
+            start += chunk.len();
+            let tx = tx.clone();
+            let (map, failed) = (&map, &failed);
+            s.spawn(move || {
+                for (index, item) in chunk.into_iter().enumerate() {
+                    if policy.stop_early(failed) {
+                        break;
+                    }
+                    let outcome = run_job(|| map(item));
+                    if outcome.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    tx.send((offset + index, outcome)).expect("Reducer hung up");
+                }
+            });
+        }
+        drop(tx);
+
+        let mut reduced = init;
+        let mut failures = Vec::new();
+        for (index, outcome) in rx {
+            match outcome {
+                Ok(mapped) => reduced = reduce(reduced, mapped),
+                Err(message) => failures.push(JobFailure { index, message }),
+            }
+        }
+        (reduced, failures)
+    });
+
+    policy.settle(reduced, failures)
+}
+
+pub fn map_reduce_histogram() {
+    let values: Vec<u32> = (0..1000).map(|i| (i * 37) % 500).collect();
+
+    let histogram = map_reduce(
+        values,
+        4,
+        |value| value / 100,
+        |mut counts: HashMap<u32, usize>, bucket| {
+            *counts.entry(bucket).or_default() += 1;
+            counts
+        },
+        HashMap::new(),
+    );
+
+    let mut buckets: Vec<_> = histogram.into_iter().collect();
+    buckets.sort();
+    println!("histogram buckets: {:?}", buckets);
+}
+
+pub fn available_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+//Each thread scans its own chunk, but before looking at every element it checks the shared flag.
+// As soon as any thread finds a match it sets the flag and everybody else stops early.
+pub fn parallel_any<P>(data: &[i32], pred: P) -> bool
+where
+    P: Fn(&i32) -> bool + Sync,
+{
+    if data.is_empty() {
+        return false;
+    }
+
+    let found = AtomicBool::new(false);
+    let chunk_size = data.len().div_ceil(available_threads());
+
+    thread::scope(|s| {
+        for chunk in data.chunks(chunk_size) {
+            let found = &found;
+            let pred = &pred;
+            s.spawn(move || {
+                for value in chunk {
+                    //Relaxed is enough here, the flag is only a hint to stop and the real answer
+                    // is read after scope() has joined every thread.
+                    if found.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    if pred(value) {
+                        found.store(true, Ordering::Relaxed);
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    found.into_inner()
+}
+
+//Everything matching is the same as nothing failing to match, so this is just parallel_any()
+// looking for the first counterexample.
+pub fn parallel_all<P>(data: &[i32], pred: P) -> bool
+where
+    P: Fn(&i32) -> bool + Sync,
+{
+    !parallel_any(data, |value| !pred(value))
+}
+
+//Same early exit idea as parallel_any(), but this hands back the item that matched, so the chunks
+// have to be owned. When several threads find a match at about the same time any one of them can
+// win, so "first" only means first found, not first in the input.
+pub fn parallel_find<T, F>(items: Vec<T>, workers: usize, pred: F) -> Option<T>
+where
+    T: Send,
+    F: Fn(&T) -> bool + Send + Sync,
+{
+    let found = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        let handles: Vec<_> = split_into_chunks(items, workers)
+            .into_iter()
+            .map(|chunk| {
+                let found = &found;
+                let pred = &pred;
+                s.spawn(move || {
+                    let hit = chunk
+                        .into_iter()
+                        .take_while(|_| !found.load(Ordering::Relaxed))
+                        .find(|item| pred(item));
+                    if hit.is_some() {
+                        found.store(true, Ordering::Relaxed);
+                    }
+                    hit
+                })
+            })
+            .collect();
+
+        //Every handle has to be joined, so this can't stop at the first Some like find() would.
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Find thread crashed"))
+            .fold(None, |first, hit| first.or(hit))
+    })
+}
+
+pub fn parallel_any_and_all() {
+    let data: Vec<i32> = (0..1_000_000).collect();
+    let checked = AtomicUsize::new(0);
+
+    //The very first element fails, so only a handful of elements should ever be checked.
+    let all_positive = parallel_all(&data, |&value| {
+        checked.fetch_add(1, Ordering::Relaxed);
+        value > 0
+    });
+    println!(
+        "all positive: {all_positive}, checked {} of {} elements",
+        checked.swap(0, Ordering::Relaxed),
+        data.len()
+    );
+
+    let any_small = parallel_any(&data, |&value| {
+        checked.fetch_add(1, Ordering::Relaxed);
+        value == 3
+    });
+    println!(
+        "any equal to 3: {any_small}, checked {} of {} elements",
+        checked.load(Ordering::Relaxed),
+        data.len()
+    );
+
+    println!("all below 2 million: {}", parallel_all(&data, |&value| value < 2_000_000));
+    println!("any negative: {}", parallel_any(&data, |&value| value < 0));
+}
+
+pub fn finding_in_parallel() {
+    let items: Vec<u64> = (0..2_000_000).collect();
+    let total = items.len();
+    let scanned = AtomicUsize::new(0);
+
+    let found = parallel_find(items, 4, |&value| {
+        scanned.fetch_add(1, Ordering::Relaxed);
+        value % 1_000 == 777
+    });
+    println!(
+        "parallel_find found {:?}, scanned {} of {total} items",
+        found,
+        scanned.load(Ordering::Relaxed)
+    );
+
+    let missing = parallel_find((0..1_000u64).collect(), 4, |&value| value > 5_000);
+    println!("parallel_find with no match: {:?}", missing);
+}
+
+//Each thread builds its own partial map with no locking at all, then the partial maps are merged
+// on the calling thread. Merging in chunk order keeps the items in each group in their original
+// relative order. A panic in key() is passed on to the caller.
+pub fn parallel_group_by<T, K, F>(items: Vec<T>, key: F, threads: usize) -> HashMap<K, Vec<T>>
+where
+    T: Send,
+    K: Eq + Hash + Send,
+    F: Fn(&T) -> K + Sync,
+{
+    let policy = FailurePolicy::PanicOnFirst;
+    unwrap_panic_on_first(parallel_group_by_with_policy(items, key, threads, policy))
+}
+
+//parallel_group_by() with the choice of what a panicking key() does. With ContinueIgnore the
+// item ends up in no group at all.
+pub fn parallel_group_by_with_policy<T, K, F>(
+    items: Vec<T>,
+    key: F,
+    threads: usize,
+    policy: FailurePolicy,
+) -> Result<HashMap<K, Vec<T>>, Vec<JobFailure>>
+where
+    T: Send,
+    K: Eq + Hash + Send,
+    F: Fn(&T) -> K + Sync,
+{
+    let failed = AtomicBool::new(false);
+    let mut start = 0;
+
+    let partials: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = split_into_chunks(items, threads)
+            .into_iter()
+            .map(|chunk| {
+                let offset = start;
+                start += chunk.len();
+                let (key, failed) = (&key, &failed);
+                s.spawn(move || {
+                    let mut partial: HashMap<K, Vec<T>> = HashMap::new();
+                    let mut failures = Vec::new();
+                    for (index, item) in chunk.into_iter().enumerate() {
+                        if policy.stop_early(failed) {
+                            break;
+                        }
+                        match run_job(|| key(&item)) {
+                            Ok(key) => partial.entry(key).or_default().push(item),
+                            Err(message) => {
+                                failed.store(true, Ordering::Relaxed);
+                                failures.push(JobFailure {
+                                    index: offset + index,
+                                    message,
+                                });
+                            }
+                        }
+                    }
+                    (partial, failures)
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Group by thread crashed outside of a job"))
+            .collect()
+    });
+
+    let mut groups: HashMap<K, Vec<T>> = HashMap::new();
+    let mut all_failures = Vec::new();
+    for (partial, mut failures) in partials {
+        for (key, mut items) in partial {
+            groups.entry(key).or_default().append(&mut items);
+        }
+        all_failures.append(&mut failures);
+    }
+
+    policy.settle(groups, all_failures)
+}
+
+pub fn grouping_in_parallel() {
+    let numbers: Vec<u32> = (0..30).collect();
+
+    let mut sequential: HashMap<u32, Vec<u32>> = HashMap::new();
+    for &n in &numbers {
+        sequential.entry(n % 3).or_default().push(n);
+    }
+
+    let parallel = parallel_group_by(numbers, |n| n % 3, 4);
+
+    for remainder in 0..3 {
+        println!("n % 3 == {remainder}: {:?}", parallel[&remainder]);
+    }
+    println!("matches sequential group by: {}", parallel == sequential);
+}
+
+//Each chunk should be big enough that claiming it costs next to nothing compared to the work,
+// but there should be several chunks per thread so that a thread that finishes early can pick up
+// more work instead of sitting idle while one slow chunk holds everything up.
+const MIN_ADAPTIVE_CHUNK: usize = 16;
+const CHUNKS_PER_THREAD: usize = 4;
+
+fn adaptive_chunk_size(items: usize, threads: usize) -> usize {
+    (items / (threads * CHUNKS_PER_THREAD))
+        .max(MIN_ADAPTIVE_CHUNK)
+        .min(items)
+        .max(1)
+}
+
+//Threads don't get a fixed share of the items up front. Instead they keep claiming the next
+// unprocessed chunk through a shared counter until there are none left. The chunk size that was
+// picked is returned along with the results so it can be inspected. A panic in `f` is passed on
+// to the caller.
+pub fn parallel_map_adaptive<T, R, F>(items: &[T], f: F) -> (Vec<R>, usize)
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let policy = FailurePolicy::PanicOnFirst;
+    unwrap_panic_on_first(parallel_map_adaptive_with_policy(items, policy, f))
+}
+
+//parallel_map_adaptive() with the choice of what a panicking call to `f` does, the same way
+// parallel_map() handles it.
+pub fn parallel_map_adaptive_with_policy<T, R, F>(
+    items: &[T],
+    policy: FailurePolicy,
+    f: F,
+) -> Result<(Vec<R>, usize), Vec<JobFailure>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let threads = available_threads();
+    let chunk_size = adaptive_chunk_size(items.len(), threads);
+    let chunks: Vec<&[T]> = items.chunks(chunk_size).collect();
+    let next_chunk = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+
+    let mut mapped: Vec<(usize, Vec<Result<R, String>>)> = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads.min(chunks.len()))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = Vec::new();
+                    while !policy.stop_early(&failed) {
+                        let index = next_chunk.fetch_add(1, Ordering::Relaxed);
+                        let Some(chunk) = chunks.get(index) else {
+                            break;
+                        };
+                        let mut outcomes = Vec::with_capacity(chunk.len());
+                        for item in chunk.iter() {
+                            if policy.stop_early(&failed) {
+                                break;
+                            }
+                            let outcome = run_job(|| f(item));
+                            if outcome.is_err() {
+                                failed.store(true, Ordering::Relaxed);
+                            }
+                            outcomes.push(outcome);
+                        }
+                        done.push((index, outcomes));
+                    }
+                    done
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Adaptive map thread crashed outside of a job"))
+            .collect()
+    });
+
+    mapped.sort_by_key(|(index, _)| *index);
+    let mut results = Vec::with_capacity(items.len());
+    let mut failures = Vec::new();
+    for (chunk_index, outcomes) in mapped {
+        for (offset, outcome) in outcomes.into_iter().enumerate() {
+            match outcome {
+                Ok(result) => results.push(result),
+                Err(message) => failures.push(JobFailure {
+                    index: chunk_index * chunk_size + offset,
+                    message,
+                }),
+            }
+        }
+    }
+
+    policy.settle((results, chunk_size), failures)
+}
+
+pub fn mapping_with_adaptive_chunks() {
+    for len in [0, 5, 1000, 100_000] {
+        let items: Vec<u64> = (0..len).collect();
+        let (squares, chunk_size) = parallel_map_adaptive(&items, |n| n * n);
+
+        let correct = squares.iter().zip(&items).all(|(square, n)| *square == n * n);
+        println!(
+            "adaptive map over {len} items: chunk size {chunk_size}, {} results, correct: {correct}",
+            squares.len()
+        );
+    }
+}
+
+//Every task gets its own thread, and sends back its result along with the id of the thread it
+// ran on. ThreadIds are never reused while the program is running, so one task per thread means
+// one distinct key per task.
+pub fn spawn_with_ids<F>(tasks: Vec<F>) -> HashMap<thread::ThreadId, i32>
+where
+    F: FnOnce() -> i32 + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+
+    for task in tasks {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let result = task();
+            tx.send((thread::current().id(), result)).expect("Collector hung up");
+        });
+    }
+    drop(tx);
+
+    rx.into_iter().collect()
+}
+
+pub fn mapping_thread_ids_to_results() {
+    let tasks: Vec<_> = (1..=4).map(|i| move || i * 10).collect();
+    let results = spawn_with_ids(tasks);
+
+    println!("4 tasks ran on {} distinct threads", results.len());
+    for (id, result) in &results {
+        println!("{:?} returned {result}", id);
+    }
+}
+
+//The spawn everything, then join everything in order boilerplate that shows up all over this
+// repo. Joining in spawn order is what keeps the results in the same order as the closures, no
+// matter which thread finishes first. A panic in any of the closures is passed on to the caller.
+pub fn spawn_collect<T, F>(closures: Vec<F>) -> Vec<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let policy = FailurePolicy::PanicOnFirst;
+    unwrap_panic_on_first(spawn_collect_with_policy(closures, policy))
+}
+
+//spawn_collect() with the choice of what a panicking closure does. Every closure is already
+// running by the time one of them can fail, so PanicOnFirst can't skip any of them here, it only
+// decides what the caller gets back.
+pub fn spawn_collect_with_policy<T, F>(
+    closures: Vec<F>,
+    policy: FailurePolicy,
+) -> Result<Vec<T>, Vec<JobFailure>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let handles: Vec<_> = closures.into_iter().map(thread::spawn).collect();
+
+    let mut results = Vec::with_capacity(handles.len());
+    let mut failures = Vec::new();
+    for (index, handle) in handles.into_iter().enumerate() {
+        match handle.join() {
+            Ok(result) => results.push(result),
+            Err(panic) => failures.push(JobFailure {
+                index,
+                message: panic_message(&*panic),
+            }),
+        }
+    }
+
+    policy.settle(results, failures)
+}
+
+pub fn collecting_spawned_results() {
+    //Every closure has its own type, boxing them is what lets them share a Vec.
+    let closures: Vec<Box<dyn FnOnce() -> &'static str + Send>> = vec![
+        Box::new(|| {
+            thread::sleep(Duration::from_millis(30));
+            "first"
+        }),
+        Box::new(|| {
+            thread::sleep(Duration::from_millis(10));
+            "second"
+        }),
+        Box::new(|| "third"),
+    ];
+
+    let results = spawn_collect(closures);
+    println!("spawn_collect results: {:?}", results);
+}
+
+//The token is checked before every item rather than once per chunk, so a cancel takes effect
+// within one item's worth of work on every thread. Items that got skipped come back as None in
+// their original position, and whatever had already finished is kept.
+pub fn parallel_map_cancellable<T, R, F>(
+    items: Vec<T>,
+    f: F,
+    token: CancellationToken,
+) -> Vec<Option<R>>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    thread::scope(|s| {
+        let handles: Vec<_> = split_into_chunks(items, available_threads())
+            .into_iter()
+            .map(|chunk| {
+                let f = &f;
+                let token = &token;
+                s.spawn(move || {
+                    chunk
+                        .into_iter()
+                        .map(|item| (!token.is_cancelled()).then(|| f(item)))
+                        .collect::<Vec<Option<R>>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Cancellable map thread crashed"))
+            .collect()
+    })
+}
+
+pub fn cancelling_a_parallel_map() {
+    let trigger = ShutdownTrigger::new();
+
+    //Item 25 pulls the plug on everything that hasn't started yet.
+    let results = parallel_map_cancellable(
+        (0..100u64).collect(),
+        |item| {
+            if item == 25 {
+                trigger.fire();
+            }
+            item * 2
+        },
+        trigger.token(),
+    );
+
+    let finished = results.iter().filter(|result| result.is_some()).count();
+    println!(
+        "cancelled parallel map: {finished} finished, {} skipped, items 24 to 27: {:?}",
+        results.len() - finished,
+        &results[24..28]
+    );
+}
+
+//What the helpers in this module do when one of the jobs panics. A job is one call of the closure
+// the helper was given, and its index is the position of its item (or closure) in the input.
+// parallel_map() and the *_with_policy() versions of map_reduce(), parallel_group_by(),
+// parallel_map_adaptive() and spawn_collect() all take one, the plain versions use PanicOnFirst.
+#[derive(Debug, Clone, Copy)]
+pub enum FailurePolicy {
+    //Workers stop picking up new items once anything has failed, and the failure with the lowest
+    // index is then re-raised on the calling thread.
+    PanicOnFirst,
+    //Every item is attempted. If any failed the result is Err with all of the failures.
+    CollectErrors,
+    //Every item is attempted and failed items are simply left out of the result.
+    ContinueIgnore,
+}
+
+#[derive(Debug)]
+pub struct JobFailure {
+    pub index: usize,
+    pub message: String,
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("panicked with a non-string payload")
+    }
+}
+
+//AssertUnwindSafe is fine here, a panicking job only leaves its own item unfinished.
+fn run_job<R>(job: impl FnOnce() -> R) -> Result<R, String> {
+    panic::catch_unwind(AssertUnwindSafe(job)).map_err(|panic| panic_message(&*panic))
+}
+
+impl FailurePolicy {
+    //Checked by workers before every job. Only PanicOnFirst gives up on the remaining items.
+    fn stop_early(self, failed: &AtomicBool) -> bool {
+        matches!(self, FailurePolicy::PanicOnFirst) && failed.load(Ordering::Relaxed)
+    }
+
+    //Turns what a helper built out of the jobs that worked, plus the failures, into its answer.
+    // Threads report failures in whatever order they finish, so they are sorted by index first.
+    fn settle<R>(self, value: R, mut failures: Vec<JobFailure>) -> Result<R, Vec<JobFailure>> {
+        failures.sort_by_key(|failure| failure.index);
+        match self {
+            FailurePolicy::PanicOnFirst => match failures.into_iter().next() {
+                Some(failure) => panic!("Job {} failed: {}", failure.index, failure.message),
+                None => Ok(value),
+            },
+            FailurePolicy::CollectErrors if !failures.is_empty() => Err(failures),
+            FailurePolicy::CollectErrors | FailurePolicy::ContinueIgnore => Ok(value),
+        }
+    }
+}
+
+//For the helpers that always use PanicOnFirst, settle() has already panicked on any failure.
+fn unwrap_panic_on_first<R>(result: Result<R, Vec<JobFailure>>) -> R {
+    result.expect("PanicOnFirst panics instead of returning Err")
+}
+
+//Each item runs inside catch_unwind() so one bad item doesn't take its whole chunk down with it.
+pub fn parallel_map<T, R, F>(
+    items: Vec<T>,
+    threads: usize,
+    policy: FailurePolicy,
+    f: F,
+) -> Result<Vec<R>, Vec<JobFailure>>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let failed = AtomicBool::new(false);
+    let mut start = 0;
+
+    let outcomes: Vec<(usize, Result<R, String>)> = thread::scope(|s| {
+        let handles: Vec<_> = split_into_chunks(items, threads)
+            .into_iter()
+            .map(|chunk| {
+                let offset = start;
+                start += chunk.len();
+                let (f, failed) = (&f, &failed);
+
+                s.spawn(move || {
+                    let mut outcomes = Vec::with_capacity(chunk.len());
+                    for (index, item) in chunk.into_iter().enumerate() {
+                        if policy.stop_early(failed) {
+                            break;
+                        }
+                        let outcome = run_job(|| f(item));
+                        if outcome.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        outcomes.push((offset + index, outcome));
+                    }
+                    outcomes
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Map thread crashed outside of a job"))
+            .collect()
+    });
+
+    let mut results = Vec::with_capacity(outcomes.len());
+    let mut failures = Vec::new();
+    for (index, outcome) in outcomes {
+        match outcome {
+            Ok(result) => results.push(result),
+            Err(message) => failures.push(JobFailure { index, message }),
+        }
+    }
+
+    policy.settle(results, failures)
+}
+
+pub fn handling_failures_in_parallel_map() {
+    //Dividing by zero panics, so item 3 is the one job that fails.
+    let divide = |divisor: i32| 60 / divisor;
+    let items = || vec![1, 2, 3, 0, 4, 5];
+
+    for policy in [FailurePolicy::CollectErrors, FailurePolicy::ContinueIgnore] {
+        println!("{:?}: {:?}", policy, parallel_map(items(), 3, policy, divide));
+    }
+
+    let panicked = panic::catch_unwind(|| {
+        parallel_map(items(), 3, FailurePolicy::PanicOnFirst, divide)
+    });
+    let message = panicked.map_err(|panic| panic_message(&*panic));
+    println!("PanicOnFirst: {:?}", message);
+}
+
+//Every item gets its own scoped thread, but the permit is taken on the calling thread before the
+// thread is spawned and only given back once `f` returns. So the loop itself stalls whenever
+// `concurrency` items are already in flight, which also keeps the number of live threads down.
+pub fn for_each_concurrent(
+    items: Vec<i32>,
+    concurrency: usize,
+    f: impl Fn(i32) + Send + Sync + Clone,
+) {
+    assert!(concurrency > 0, "Nothing could ever run with no permits");
+    let semaphore = Semaphore::new(concurrency);
+
+    thread::scope(|s| {
+        for item in items {
+            let permit = semaphore.acquire();
+            let f = f.clone();
+            s.spawn(move || {
+                f(item);
+                drop(permit);
+            });
+        }
+    });
+}
+
+pub fn throttling_concurrent_work() {
+    let in_flight = AtomicUsize::new(0);
+    let peak = AtomicUsize::new(0);
+    let processed = AtomicUsize::new(0);
+
+    for_each_concurrent((0..20).collect(), 3, |item| {
+        let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        peak.fetch_max(now, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(2 + item as u64 % 3));
+        processed.fetch_add(1, Ordering::SeqCst);
+        in_flight.fetch_sub(1, Ordering::SeqCst);
+    });
+
+    println!(
+        "for_each_concurrent processed {} items, peak in flight {} with a limit of 3",
+        processed.load(Ordering::SeqCst),
+        peak.load(Ordering::SeqCst)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn map_reduce_histogram_matches_sequential() {
+        let values: Vec<u32> = (0..1000).map(|i| (i * 37) % 500).collect();
+
+        let mut expected: HashMap<u32, usize> = HashMap::new();
+        for value in &values {
+            *expected.entry(value / 100).or_default() += 1;
+        }
+
+        let histogram = map_reduce(
+            values,
+            4,
+            |value| value / 100,
+            |mut counts: HashMap<u32, usize>, bucket| {
+                *counts.entry(bucket).or_default() += 1;
+                counts
+            },
+            HashMap::new(),
+        );
+
+        assert_eq!(histogram, expected);
+        assert_eq!(histogram.values().sum::<usize>(), 1000);
+    }
+
+    #[test]
+    fn parallel_any_and_all_answers() {
+        let data: Vec<i32> = (0..1000).collect();
+
+        assert!(parallel_all(&data, |&value| value >= 0));
+        assert!(!parallel_all(&data, |&value| value < 999));
+        assert!(parallel_any(&data, |&value| value == 999));
+        assert!(!parallel_any(&data, |&value| value < 0));
+
+        //Nothing in an empty slice matches, and everything in it does.
+        assert!(!parallel_any(&[], |_| true));
+        assert!(parallel_all(&[], |_| false));
+    }
+
+    #[test]
+    fn parallel_any_and_all_stop_early() {
+        let data: Vec<i32> = (0..1_000_000).collect();
+        let checked = AtomicUsize::new(0);
+
+        //A failing element right at the start for all().
+        let all_positive = parallel_all(&data, |&value| {
+            checked.fetch_add(1, Ordering::Relaxed);
+            value > 0
+        });
+        assert!(!all_positive);
+        assert!(checked.swap(0, Ordering::Relaxed) < data.len());
+
+        //A matching element right at the start for any().
+        let any_small = parallel_any(&data, |&value| {
+            checked.fetch_add(1, Ordering::Relaxed);
+            value == 3
+        });
+        assert!(any_small);
+        assert!(checked.load(Ordering::Relaxed) < data.len());
+    }
+
+    #[test]
+    fn parallel_group_by_matches_sequential() {
+        let numbers: Vec<u32> = (0..100).collect();
+
+        let mut sequential: HashMap<u32, Vec<u32>> = HashMap::new();
+        for &n in &numbers {
+            sequential.entry(n % 3).or_default().push(n);
+        }
+
+        for threads in [1, 3, 4, 7] {
+            assert_eq!(parallel_group_by(numbers.clone(), |n| n % 3, threads), sequential);
+        }
+        assert!(parallel_group_by(Vec::<u32>::new(), |n| n % 3, 4).is_empty());
+    }
+
+    #[test]
+    fn parallel_map_adaptive_matches_sequential() {
+        for len in [0, 1, 5, 17, 1000, 100_000] {
+            let items: Vec<u64> = (0..len).collect();
+            let sequential: Vec<u64> = items.iter().map(|n| n * n).collect();
+
+            let (squares, chunk_size) = parallel_map_adaptive(&items, |n| n * n);
+            assert_eq!(squares, sequential, "len {len}");
+            assert!(chunk_size >= 1);
+        }
+    }
+
+    #[test]
+    fn adaptive_chunks_are_bounded_by_the_input() {
+        assert_eq!(adaptive_chunk_size(0, 4), 1);
+        assert_eq!(adaptive_chunk_size(5, 4), 5);
+        assert_eq!(adaptive_chunk_size(1000, 4), MIN_ADAPTIVE_CHUNK.max(1000 / 16));
+    }
+
+    #[test]
+    fn every_result_comes_from_its_own_thread() {
+        let tasks: Vec<_> = (1..=6).map(|i| move || i * 10).collect();
+        let results = spawn_with_ids(tasks);
+
+        //Keyed by ThreadId, so 6 entries means 6 distinct ids.
+        assert_eq!(results.len(), 6);
+        assert!(!results.contains_key(&thread::current().id()));
+        let mut values: Vec<i32> = results.into_values().collect();
+        values.sort();
+        assert_eq!(values, vec![10, 20, 30, 40, 50, 60]);
+    }
+
+    #[test]
+    fn parallel_find_returns_a_match_or_none() {
+        let items: Vec<u32> = (0..10_000).collect();
+        let hit = parallel_find(items.clone(), 4, |&value| value % 1_000 == 999);
+        let hit = hit.expect("There are ten matches");
+        assert_eq!(hit % 1_000, 999);
+
+        assert_eq!(parallel_find(items.clone(), 4, |&value| value == 7_777), Some(7_777));
+        assert_eq!(parallel_find(items, 4, |&value| value > 10_000), None);
+        assert_eq!(parallel_find(Vec::<u32>::new(), 4, |_| true), None);
+    }
+
+    #[test]
+    fn parallel_find_stops_scanning_after_a_match() {
+        let items: Vec<u32> = (0..1_000_000).collect();
+        let total = items.len();
+        let scanned = AtomicUsize::new(0);
+
+        //The match is right at the start of the first chunk. Even if the other threads finished
+        // their whole chunks before it was found, that first chunk stops after 4 items.
+        let hit = parallel_find(items, 4, |&value| {
+            scanned.fetch_add(1, Ordering::Relaxed);
+            value == 3
+        });
+        assert_eq!(hit, Some(3));
+        assert!(scanned.load(Ordering::Relaxed) < total);
+    }
+
+    #[test]
+    fn spawn_collect_keeps_spawn_order() {
+        //Closure i only finishes after closure i + 1 has, so they finish in reverse order.
+        let count = 5;
+        let finish_order = Arc::new(Mutex::new(Vec::new()));
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..count).map(|_| mpsc::channel()).unzip();
+        let mut receivers: Vec<_> = receivers.into_iter().map(Some).collect();
+
+        let closures: Vec<_> = senders
+            .into_iter()
+            .enumerate()
+            .map(|(i, done)| {
+                let next = receivers.get_mut(i + 1).and_then(Option::take);
+                let finish_order = Arc::clone(&finish_order);
+                move || {
+                    if let Some(next) = next {
+                        next.recv().unwrap();
+                    }
+                    finish_order.lock().unwrap().push(i);
+                    done.send(()).unwrap();
+                    i * 10
+                }
+            })
+            .collect();
+
+        let results = spawn_collect(closures);
+        assert_eq!(results, vec![0, 10, 20, 30, 40]);
+        assert_eq!(*finish_order.lock().unwrap(), vec![4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn cancelling_skips_the_items_not_yet_started() {
+        let trigger = ShutdownTrigger::new();
+        let calls = AtomicUsize::new(0);
+
+        //Item 0 is the first thing the first worker does, so at least the rest of its chunk is
+        // skipped however the other workers are doing.
+        let results = parallel_map_cancellable(
+            (0..100u64).collect(),
+            |item| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                if item == 0 {
+                    trigger.fire();
+                }
+                item * 2
+            },
+            trigger.token(),
+        );
+
+        assert_eq!(results.len(), 100);
+        assert_eq!(results[0], Some(0));
+        assert_eq!(results[1], None);
+        let finished = results.iter().filter(|result| result.is_some()).count();
+        assert_eq!(finished, calls.load(Ordering::SeqCst));
+        assert!(finished < 100);
+        for (item, result) in results.iter().enumerate() {
+            assert!(result.is_none_or(|doubled| doubled == item as u64 * 2));
+        }
+    }
+
+    #[test]
+    fn an_already_cancelled_map_does_no_work() {
+        let trigger = ShutdownTrigger::new();
+        trigger.fire();
+        let results =
+            parallel_map_cancellable((0..50).collect(), |item: i32| item, trigger.token());
+        assert_eq!(results, vec![None; 50]);
+    }
+
+    //Item 3 is the one job that fails, dividing by zero panics.
+    const DIVISORS: [i32; 6] = [1, 2, 3, 0, 4, 5];
+
+    fn divide(divisor: i32) -> i32 {
+        60 / divisor
+    }
+
+    fn sum(total: i32, value: i32) -> i32 {
+        total + value
+    }
+
+    fn divide_closures() -> Vec<impl FnOnce() -> i32 + Send + 'static> {
+        DIVISORS.iter().map(|&divisor| move || divide(divisor)).collect()
+    }
+
+    fn assert_only_job_3_failed(failures: Vec<JobFailure>) {
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].index, 3);
+        assert!(failures[0].message.contains("divide by zero"), "{}", failures[0].message);
+    }
+
+    fn panic_message_of<R>(f: impl FnOnce() -> R) -> String {
+        let panic = panic::catch_unwind(AssertUnwindSafe(f)).err().expect("Should have panicked");
+        panic_message(&*panic)
+    }
+
+    #[test]
+    fn panic_on_first_reraises_the_failure_on_the_caller() {
+        let policy = FailurePolicy::PanicOnFirst;
+        let expected = "Job 3 failed: attempt to divide by zero";
+
+        let message = panic_message_of(|| parallel_map(DIVISORS.to_vec(), 3, policy, divide));
+        assert_eq!(message, expected);
+        //The helpers without a policy argument are the PanicOnFirst versions.
+        let message = panic_message_of(|| map_reduce(DIVISORS.to_vec(), 3, divide, sum, 0));
+        assert_eq!(message, expected);
+        let message = panic_message_of(|| parallel_group_by(DIVISORS.to_vec(), |&d| divide(d), 3));
+        assert_eq!(message, expected);
+        let message = panic_message_of(|| parallel_map_adaptive(&DIVISORS, |&d| divide(d)));
+        assert_eq!(message, expected);
+        assert_eq!(panic_message_of(|| spawn_collect(divide_closures())), expected);
+    }
+
+    #[test]
+    fn panic_on_first_stops_picking_up_items() {
+        //One worker, so everything after item 3 comes after the failure.
+        let calls = AtomicUsize::new(0);
+        let counted = |divisor: i32| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            divide(divisor)
+        };
+
+        let policy = FailurePolicy::PanicOnFirst;
+        panic_message_of(|| parallel_map(DIVISORS.to_vec(), 1, policy, counted));
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 4);
+        panic_message_of(|| map_reduce(DIVISORS.to_vec(), 1, counted, sum, 0));
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 4);
+        panic_message_of(|| parallel_group_by(DIVISORS.to_vec(), |&d| counted(d), 1));
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn collect_errors_returns_every_failure() {
+        let policy = FailurePolicy::CollectErrors;
+
+        assert_only_job_3_failed(parallel_map(DIVISORS.to_vec(), 3, policy, divide).unwrap_err());
+        assert_only_job_3_failed(
+            map_reduce_with_policy(DIVISORS.to_vec(), 3, policy, divide, sum, 0).unwrap_err(),
+        );
+        assert_only_job_3_failed(
+            parallel_group_by_with_policy(DIVISORS.to_vec(), |&d| divide(d), 3, policy)
+                .unwrap_err(),
+        );
+        assert_only_job_3_failed(
+            parallel_map_adaptive_with_policy(&DIVISORS, policy, |&d| divide(d)).unwrap_err(),
+        );
+        assert_only_job_3_failed(spawn_collect_with_policy(divide_closures(), policy).unwrap_err());
+
+        //Two failures come back sorted by index, whichever thread hit its failure first.
+        let failures = parallel_map(vec![0, 1, 1, 1, 0], 5, policy, divide).unwrap_err();
+        let indexes: Vec<usize> = failures.iter().map(|failure| failure.index).collect();
+        assert_eq!(indexes, vec![0, 4]);
+    }
+
+    #[test]
+    fn continue_ignore_leaves_out_the_failed_job() {
+        let policy = FailurePolicy::ContinueIgnore;
+        let expected = vec![60, 30, 20, 15, 12];
+
+        assert_eq!(parallel_map(DIVISORS.to_vec(), 3, policy, divide).unwrap(), expected);
+        let total = map_reduce_with_policy(DIVISORS.to_vec(), 3, policy, divide, sum, 0);
+        assert_eq!(total.unwrap(), 137);
+        let groups = parallel_group_by_with_policy(DIVISORS.to_vec(), |&d| divide(d) % 2, 3, policy)
+            .unwrap();
+        assert_eq!(groups[&0], vec![1, 2, 3, 5]);
+        assert_eq!(groups[&1], vec![4]);
+        assert_eq!(groups.values().map(Vec::len).sum::<usize>(), 5);
+        let (results, _) =
+            parallel_map_adaptive_with_policy(&DIVISORS, policy, |&d| divide(d)).unwrap();
+        assert_eq!(results, expected);
+        assert_eq!(spawn_collect_with_policy(divide_closures(), policy).unwrap(), expected);
+    }
+
+    #[test]
+    fn for_each_concurrent_never_exceeds_its_limit() {
+        for limit in [1, 3] {
+            let in_flight = AtomicUsize::new(0);
+            let peak = AtomicUsize::new(0);
+            let processed = Mutex::new(Vec::new());
+
+            for_each_concurrent((0..20).collect(), limit, |item| {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(2));
+                processed.lock().unwrap().push(item);
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            });
+
+            let peak = peak.load(Ordering::SeqCst);
+            assert!((1..=limit).contains(&peak), "peak {peak} with a limit of {limit}");
+            let mut processed = processed.into_inner().unwrap();
+            processed.sort();
+            assert_eq!(processed, (0..20).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,1024 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Add;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Barrier, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+use crate::parallel::split_into_chunks;
+use crate::rng::SplittableRng;
+
+#[derive(Debug)]
+pub struct LengthMismatch {
+    pub left: usize,
+    pub right: usize,
+}
+
+//Kahan summation keeps a running compensation term holding the low order bits that were lost
+// when the last value was added. Adding lots of floats naively lets that error build up.
+fn kahan_sum(values: impl Iterator<Item = f64>) -> f64 {
+    let mut sum = 0.0;
+    let mut compensation = 0.0;
+
+    for value in values {
+        let adjusted = value - compensation;
+        let next = sum + adjusted;
+        compensation = (next - sum) - adjusted;
+        sum = next;
+    }
+
+    sum
+}
+
+//Every thread gets a range of indices and computes its own compensated partial dot product. The
+// partials are then summed the same way on the calling thread.
+pub fn parallel_dot(a: &[f64], b: &[f64], threads: usize) -> Result<f64, LengthMismatch> {
+    if a.len() != b.len() {
+        return Err(LengthMismatch {
+            left: a.len(),
+            right: b.len(),
+        });
+    }
+
+    if a.is_empty() {
+        return Ok(0.0);
+    }
+
+    let chunk_size = a.len().div_ceil(threads.max(1));
+
+    let partials: Vec<f64> = thread::scope(|s| {
+        let handles: Vec<_> = a
+            .chunks(chunk_size)
+            .zip(b.chunks(chunk_size))
+            .map(|(a, b)| s.spawn(move || kahan_sum(a.iter().zip(b).map(|(x, y)| x * y))))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Dot product thread crashed"))
+            .collect()
+    });
+
+    Ok(kahan_sum(partials.into_iter()))
+}
+
+pub fn parallel_dot_product() {
+    let a: Vec<f64> = (0..100_000).map(|i| 1.0 / (i as f64 + 1.0)).collect();
+    let b: Vec<f64> = (0..100_000).map(|i| (i % 7) as f64 + 0.1).collect();
+
+    let sequential: f64 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+    let parallel = parallel_dot(&a, &b, 4).expect("Lengths are equal");
+    println!(
+        "dot product sequential: {sequential}, parallel: {parallel}, difference: {:e}",
+        (sequential - parallel).abs()
+    );
+
+    match parallel_dot(&a, &b[..10], 4) {
+        Ok(dot) => println!("unexpected dot product: {dot}"),
+        Err(LengthMismatch { left, right }) => {
+            println!("dot product refused mismatched lengths {left} and {right}")
+        }
+    }
+}
+
+//What parallel_translate() uses for inputs that aren't in the table.
+const MISSING_TRANSLATION: &str = "unknown";
+
+//The table is only ever read, so there's no need for a Mutex<T> around it. Arc<T> on its own is
+// enough to share it between threads because &HashMap is Sync. Using thread::spawn here instead
+// of thread::scope is on purpose, without scoped threads the table has to be owned by every
+// thread that uses it, which is exactly what the Arc<T> is for.
+pub fn parallel_translate(
+    inputs: Vec<u32>,
+    table: HashMap<u32, String>,
+    threads: usize,
+) -> Vec<String> {
+    let table = Arc::new(table);
+
+    let handles: Vec<_> = split_into_chunks(inputs, threads)
+        .into_iter()
+        .map(|chunk| {
+            let table = Arc::clone(&table);
+            thread::spawn(move || {
+                chunk
+                    .into_iter()
+                    .map(|input| match table.get(&input) {
+                        Some(translation) => translation.clone(),
+                        None => MISSING_TRANSLATION.to_string(),
+                    })
+                    .collect::<Vec<String>>()
+            })
+        })
+        .collect();
+
+    //Joining the handles in the order they were spawned keeps the chunks, and so the output, in
+    // input order.
+    handles
+        .into_iter()
+        .flat_map(|handle| handle.join().expect("Translation thread crashed"))
+        .collect()
+}
+
+pub fn translating_with_a_shared_table() {
+    let table: HashMap<u32, String> = [(1, "one"), (2, "two"), (3, "three")]
+        .into_iter()
+        .map(|(key, value)| (key, value.to_string()))
+        .collect();
+
+    let translated = parallel_translate(vec![3, 1, 4, 2, 2, 7, 1], table, 3);
+    println!("translated: {:?}", translated);
+}
+
+fn distance_squared(a: (f64, f64), b: (f64, f64)) -> f64 {
+    (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)
+}
+
+fn nearest_in(points: &[(f64, f64)], target: (f64, f64)) -> Option<(f64, f64)> {
+    //total_cmp() gives f64 a full ordering (NaN included), which min_by() needs.
+    points.iter().copied().min_by(|a, b| {
+        distance_squared(*a, target).total_cmp(&distance_squared(*b, target))
+    })
+}
+
+//Each thread finds the nearest point in its own chunk, then the nearest of those local winners is
+// the global answer. Comparing squared distances avoids a square root per point.
+pub fn parallel_nearest(
+    points: &[(f64, f64)],
+    target: (f64, f64),
+    threads: usize,
+) -> Option<(f64, f64)> {
+    if points.is_empty() {
+        return None;
+    }
+
+    let chunk_size = points.len().div_ceil(threads.max(1));
+
+    let local_nearest: Vec<(f64, f64)> = thread::scope(|s| {
+        let handles: Vec<_> = points
+            .chunks(chunk_size)
+            .map(|chunk| s.spawn(move || nearest_in(chunk, target)))
+            .collect();
+
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().expect("Nearest neighbor thread crashed"))
+            .collect()
+    });
+
+    nearest_in(&local_nearest, target)
+}
+
+pub fn finding_the_nearest_point() {
+    let points: Vec<(f64, f64)> = (0..1000)
+        .map(|i| ((i % 37) as f64 * 3.0, (i / 37) as f64 * 2.0))
+        .collect();
+
+    println!(
+        "nearest point to (50.2, 20.9): {:?}",
+        parallel_nearest(&points, (50.2, 20.9), 4)
+    );
+    println!("nearest point with no points: {:?}", parallel_nearest(&[], (0.0, 0.0), 4));
+}
+
+//One step of a three point averaging stencil. The two end points stay fixed.
+fn stencil_step(old: impl Fn(usize) -> f64, len: usize, index: usize) -> f64 {
+    if index == 0 || index == len - 1 {
+        old(index)
+    } else {
+        (old(index - 1) + old(index) + old(index + 1)) / 3.0
+    }
+}
+
+pub fn sequential_stencil(data: Vec<f64>, iterations: usize) -> Vec<f64> {
+    let mut current = data;
+    for _ in 0..iterations {
+        let len = current.len();
+        current = (0..len).map(|i| stencil_step(|j| current[j], len, i)).collect();
+    }
+    current
+}
+
+//Every thread owns one chunk of the array, but updating the edges of a chunk needs the values at
+// the edges of the neighboring chunks. Two things keep that correct:
+// 1) Double buffering; each iteration reads only from one buffer and writes only to the other,
+//  then they swap roles. Nobody ever reads a value that is being overwritten in the same pass.
+// 2) A Barrier between iterations; no thread starts reading the buffer that was just written
+//  until every thread has finished writing it.
+//Plain Vec<f64> buffers can't be written by several threads at once without unsafe code, so the
+// values are stored as the bits of an f64 inside AtomicU64s. Relaxed is enough because the
+// barrier already makes every write from one iteration visible to the next.
+pub fn parallel_stencil(data: Vec<f64>, iterations: usize, threads: usize) -> Vec<f64> {
+    let len = data.len();
+    if len == 0 {
+        return data;
+    }
+
+    let to_atomic = |values: &[f64]| -> Vec<AtomicU64> {
+        values.iter().map(|value| AtomicU64::new(value.to_bits())).collect()
+    };
+    let buffers = [to_atomic(&data), to_atomic(&data)];
+
+    let chunk_size = len.div_ceil(threads.clamp(1, len));
+    //Rounding the chunk size up can leave fewer chunks than threads were asked for, and the
+    // barrier has to wait for exactly as many threads as there are chunks.
+    let barrier = Barrier::new(len.div_ceil(chunk_size));
+
+    thread::scope(|s| {
+        for start in (0..len).step_by(chunk_size) {
+            let end = (start + chunk_size).min(len);
+            let buffers = &buffers;
+            let barrier = &barrier;
+            s.spawn(move || {
+                for iteration in 0..iterations {
+                    let old = &buffers[iteration % 2];
+                    let new = &buffers[(iteration + 1) % 2];
+                    let read = |j: usize| f64::from_bits(old[j].load(Ordering::Relaxed));
+
+                    for (i, cell) in new.iter().enumerate().take(end).skip(start) {
+                        cell.store(stencil_step(read, len, i).to_bits(), Ordering::Relaxed);
+                    }
+
+                    barrier.wait();
+                }
+            });
+        }
+    });
+
+    buffers[iterations % 2]
+        .iter()
+        .map(|value| f64::from_bits(value.load(Ordering::Relaxed)))
+        .collect()
+}
+
+pub fn parallel_stencil_with_barriers() {
+    let data: Vec<f64> = (0..20).map(|i| if i % 5 == 0 { 10.0 } else { 0.0 }).collect();
+
+    let sequential = sequential_stencil(data.clone(), 5);
+    let parallel = parallel_stencil(data.clone(), 5, 4);
+    let uneven = parallel_stencil(data, 5, 6);
+
+    let formatted: Vec<String> = parallel.iter().map(|value| format!("{:.2}", value)).collect();
+    println!("stencil after 5 iterations: {}", formatted.join(" "));
+    println!(
+        "stencil matches the sequential version: {}",
+        parallel == sequential && uneven == sequential
+    );
+}
+
+//A row is split on commas and each field is trimmed. A row that is blank or has an empty field
+// (like "a,,b" or a trailing comma) counts as malformed and comes out as an empty Vec, that
+// way one bad row doesn't throw off the positions of all the rows after it.
+fn parse_row(row: &str) -> Vec<String> {
+    let fields: Vec<String> = row.split(',').map(|field| field.trim().to_string()).collect();
+
+    if fields.iter().any(String::is_empty) {
+        Vec::new()
+    } else {
+        fields
+    }
+}
+
+pub fn parallel_parse_rows(rows: Vec<String>, threads: usize) -> Vec<Vec<String>> {
+    thread::scope(|s| {
+        let handles: Vec<_> = split_into_chunks(rows, threads)
+            .into_iter()
+            .map(|chunk| {
+                s.spawn(move || chunk.iter().map(|row| parse_row(row)).collect::<Vec<_>>())
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Row parsing thread crashed"))
+            .collect()
+    })
+}
+
+pub fn parsing_rows_in_parallel() {
+    let rows: Vec<String> = [
+        "name, age, city",
+        "alice, 30, paris",
+        "bob,,berlin",
+        "carol, 41, lima",
+        "",
+        "dave, 25, oslo",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect();
+
+    for (index, fields) in parallel_parse_rows(rows, 3).iter().enumerate() {
+        println!("row {index}: {:?}", fields);
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+//gcd(gcd(a, b), c) == gcd(a, gcd(b, c)), so it doesn't matter how the values get split up. Each
+// thread folds its own chunk and then the partial results get folded together. gcd(0, x) == x,
+// which makes 0 the starting value, and also what comes back for an empty input.
+pub fn parallel_gcd(values: Vec<u64>, threads: usize) -> u64 {
+    let partials: Vec<u64> = thread::scope(|s| {
+        let handles: Vec<_> = split_into_chunks(values, threads)
+            .into_iter()
+            .map(|chunk| s.spawn(move || chunk.into_iter().fold(0, gcd)))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("GCD thread crashed"))
+            .collect()
+    });
+
+    partials.into_iter().fold(0, gcd)
+}
+
+pub fn reducing_with_gcd() {
+    let common_factor: Vec<u64> = (1..=1000).map(|i| i * 84).collect();
+    println!("gcd of multiples of 84: {}", parallel_gcd(common_factor, 4));
+    println!("gcd of coprime values: {}", parallel_gcd(vec![9, 28, 25, 121, 13], 3));
+}
+
+fn count_bytes(values: &[u32], shift: u32) -> [usize; 256] {
+    let mut counts = [0; 256];
+    for value in values {
+        counts[((value >> shift) & 0xff) as usize] += 1;
+    }
+    counts
+}
+
+//One counting pass of an LSD radix sort, bucketing on the byte that starts at bit `shift`. Each
+// thread counts into its own [usize; 256] so there is nothing shared to fight over while
+// counting, and the per-thread arrays get added up at the end. A full sort would turn the totals
+// into starting offsets and scatter the values, once for each shift of 0, 8, 16 and 24.
+pub fn parallel_radix_pass(values: &[u32], shift: u32, threads: usize) -> [usize; 256] {
+    assert!(shift < 32, "A u32 only has bits 0 to 31");
+
+    if values.is_empty() {
+        return [0; 256];
+    }
+
+    let chunk_size = values.len().div_ceil(threads.max(1));
+
+    thread::scope(|s| {
+        let handles: Vec<_> = values
+            .chunks(chunk_size)
+            .map(|chunk| s.spawn(move || count_bytes(chunk, shift)))
+            .collect();
+
+        handles.into_iter().fold([0; 256], |mut totals, handle| {
+            let counts = handle.join().expect("Radix counting thread crashed");
+            for (total, count) in totals.iter_mut().zip(counts) {
+                *total += count;
+            }
+            totals
+        })
+    })
+}
+
+pub fn counting_radix_buckets() {
+    let values: Vec<u32> = (0..10_000u32).map(|i| i.wrapping_mul(2_654_435_761)).collect();
+
+    for shift in [0, 8, 16, 24] {
+        let counts = parallel_radix_pass(&values, shift, 4);
+        let busiest = counts.iter().max().expect("There are always 256 buckets");
+        println!(
+            "radix pass at shift {shift}: {} values counted, busiest bucket has {busiest}, \
+             matches sequential: {}",
+            counts.iter().sum::<usize>(),
+            counts == count_bytes(&values, shift)
+        );
+    }
+
+    let known = parallel_radix_pass(&[0x0100, 0x0101, 0x02ff, 0x0001], 8, 2);
+    println!(
+        "second byte of known values: {} ones, {} twos, {} zeros",
+        known[1], known[2], known[0]
+    );
+}
+
+//Moves the median of the first, middle and last elements to the front, so already sorted input
+// doesn't keep picking the smallest element as the pivot.
+fn move_median_to_front<T: Ord>(data: &mut [T]) {
+    let (first, middle, last) = (0, data.len() / 2, data.len() - 1);
+    let median = if (data[first] <= data[middle]) == (data[middle] <= data[last]) {
+        middle
+    } else if (data[middle] <= data[first]) == (data[first] <= data[last]) {
+        first
+    } else {
+        last
+    };
+    data.swap(0, median);
+}
+
+//Three way partition around data[0], which stays put until the very end so it can be compared
+// against without needing T: Clone. Returns (start, end) of the run equal to the pivot, with
+// everything before it smaller and everything after it bigger. Keeping the equal run out of both
+// halves is what stops lots of duplicates from making the recursion as deep as the input is long.
+fn partition<T: Ord>(data: &mut [T]) -> (usize, usize) {
+    move_median_to_front(data);
+
+    let (mut less, mut index, mut greater) = (1, 1, data.len());
+    while index < greater {
+        if data[index] < data[0] {
+            data.swap(index, less);
+            less += 1;
+            index += 1;
+        } else if data[index] > data[0] {
+            greater -= 1;
+            data.swap(index, greater);
+        } else {
+            index += 1;
+        }
+    }
+
+    data.swap(0, less - 1);
+    (less - 1, greater)
+}
+
+//Once the pivot is in place the two sides have nothing to do with each other, and split_at_mut()
+// proves that to the borrow checker. One side goes to a scoped thread and the current thread
+// takes the other. Below `cutoff` spawning costs more than it saves, so small slices are sorted
+// on the spot.
+pub fn parallel_sort<T: Ord + Send>(data: &mut [T], cutoff: usize) {
+    if data.len() <= cutoff.max(1) {
+        data.sort_unstable();
+        return;
+    }
+
+    let (equal_start, equal_end) = partition(data);
+    let (smaller, rest) = data.split_at_mut(equal_start);
+    let bigger = &mut rest[equal_end - equal_start..];
+
+    thread::scope(|s| {
+        s.spawn(|| parallel_sort(smaller, cutoff));
+        parallel_sort(bigger, cutoff);
+    });
+}
+
+pub fn sorting_in_parallel() {
+    let mut rng = SplittableRng::new(2024);
+    let mut data: Vec<u64> = (0..200_000).map(|i| i % 5_000).collect();
+    //Fisher-Yates shuffle.
+    for i in (1..data.len()).rev() {
+        let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+        data.swap(i, j);
+    }
+
+    let mut reference = data.clone();
+    let started = Instant::now();
+    reference.sort_unstable();
+    let sequential_time = started.elapsed();
+
+    let started = Instant::now();
+    parallel_sort(&mut data, 10_000);
+    let parallel_time = started.elapsed();
+
+    println!(
+        "parallel_sort of {} values matches sort_unstable: {}, took {:?} vs {:?}",
+        data.len(),
+        data == reference,
+        parallel_time,
+        sequential_time
+    );
+
+    let mut tiny = vec![3, 1, 2];
+    parallel_sort(&mut tiny, 16);
+    let mut empty: Vec<u64> = Vec::new();
+    parallel_sort(&mut empty, 16);
+    println!("parallel_sort below the cutoff: {:?}, empty input: {:?}", tiny, empty);
+}
+
+//Same sharing trick as parallel_translate(), only b is never changed so an Arc<HashSet> can be
+// read by every thread at once. This is a filter over a, so duplicates follow a: a value that
+// shows up three times in a and anywhere in b shows up three times in the result. Duplicates in b
+// make no difference because b becomes a set. The chunks are joined in order, so the result also
+// keeps a's order, although nothing should rely on that.
+pub fn parallel_intersection(a: Vec<i32>, b: Vec<i32>, threads: usize) -> Vec<i32> {
+    let b: Arc<HashSet<i32>> = Arc::new(b.into_iter().collect());
+
+    let handles: Vec<_> = split_into_chunks(a, threads)
+        .into_iter()
+        .map(|chunk| {
+            let b = Arc::clone(&b);
+            thread::spawn(move || {
+                chunk.into_iter().filter(|value| b.contains(value)).collect::<Vec<i32>>()
+            })
+        })
+        .collect();
+
+    handles
+        .into_iter()
+        .flat_map(|handle| handle.join().expect("Intersection thread crashed"))
+        .collect()
+}
+
+pub fn intersecting_in_parallel() {
+    let overlapping = parallel_intersection(vec![1, 2, 3, 4, 4, 5, 6], vec![4, 6, 8, 6, 2], 3);
+    println!("intersection of overlapping inputs: {:?}", overlapping);
+
+    let disjoint = parallel_intersection((0..100).collect(), (100..200).collect(), 4);
+    println!("intersection of disjoint inputs: {:?}", disjoint);
+}
+
+fn min_max(values: &[i32]) -> Option<(i32, i32)> {
+    let first = *values.first()?;
+    Some(values.iter().fold((first, first), |(min, max), &value| {
+        (min.min(value), max.max(value))
+    }))
+}
+
+//Both ends found in a single pass over each chunk, instead of one parallel pass for the min and
+// another for the max. The chunks are never empty, so every thread has a (min, max) to report.
+pub fn parallel_min_max(data: &[i32], threads: usize) -> Option<(i32, i32)> {
+    if data.is_empty() {
+        return None;
+    }
+
+    let chunk_size = data.len().div_ceil(threads.max(1));
+
+    thread::scope(|s| {
+        let handles: Vec<_> = data
+            .chunks(chunk_size)
+            .map(|chunk| s.spawn(move || min_max(chunk)))
+            .collect();
+
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().expect("Min max thread crashed"))
+            .reduce(|(min_a, max_a), (min_b, max_b)| (min_a.min(min_b), max_a.max(max_b)))
+    })
+}
+
+pub fn finding_min_and_max() {
+    println!("min and max of an empty slice: {:?}", parallel_min_max(&[], 4));
+    println!("min and max of a single element: {:?}", parallel_min_max(&[-3], 4));
+    println!("min and max of equal elements: {:?}", parallel_min_max(&[8; 100], 4));
+
+    let mut rng = SplittableRng::new(99);
+    let random: Vec<i32> = (0..100_000).map(|_| rng.next_u64() as i32).collect();
+    let parallel = parallel_min_max(&random, 4);
+    println!(
+        "min and max of random values: {:?}, matches sequential: {}",
+        parallel,
+        parallel == min_max(&random)
+    );
+}
+
+//The lines all borrow from `text`, which scoped threads are fine with, so nothing gets copied
+// until `f` builds its output. When the lines don't divide evenly split_into_chunks() just leaves
+// the last chunk shorter, and joining the chunks in order keeps the output in input order.
+pub fn parallel_process_lines(
+    text: &str,
+    workers: usize,
+    f: impl Fn(&str) -> String + Sync,
+) -> Vec<String> {
+    let lines: Vec<&str> = text.lines().collect();
+
+    thread::scope(|s| {
+        let handles: Vec<_> = split_into_chunks(lines, workers)
+            .into_iter()
+            .map(|chunk| {
+                let f = &f;
+                s.spawn(move || chunk.into_iter().map(f).collect::<Vec<String>>())
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Line processing thread crashed"))
+            .collect()
+    })
+}
+
+pub fn processing_lines_in_parallel() {
+    let text = "first line\nsecond line\nthird line\nfourth line\nfifth line\nsixth line\nseventh";
+
+    //7 lines over 3 workers, so the chunks have 3, 3 and 1 lines in them.
+    let upper = parallel_process_lines(text, 3, str::to_uppercase);
+    let in_order = upper
+        .iter()
+        .zip(text.lines())
+        .all(|(output, input)| *output == input.to_uppercase());
+    println!("uppercased lines: {:?}, in input order: {in_order}", upper);
+}
+
+//Like parallel_translate() this uses thread::spawn, so the needle has to be owned by every
+// thread. Arc<str> is a single allocation shared by all of them, cloning it only bumps the count.
+// Each chunk remembers where it started so the matches can carry their index in the whole input,
+// and because the chunks are joined in order the matches come out sorted by that index.
+pub fn parallel_grep(lines: Vec<String>, needle: &str, threads: usize) -> Vec<(usize, String)> {
+    let needle: Arc<str> = Arc::from(needle);
+    let mut start = 0;
+
+    let handles: Vec<_> = split_into_chunks(lines, threads)
+        .into_iter()
+        .map(|chunk| {
+            let needle = Arc::clone(&needle);
+            let offset = start;
+            start += chunk.len();
+
+            thread::spawn(move || {
+                chunk
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, line)| line.contains(&*needle))
+                    .map(|(index, line)| (offset + index, line))
+                    .collect::<Vec<(usize, String)>>()
+            })
+        })
+        .collect();
+
+    handles
+        .into_iter()
+        .flat_map(|handle| handle.join().expect("Grep thread crashed"))
+        .collect()
+}
+
+pub fn grepping_in_parallel() {
+    let lines: Vec<String> = [
+        "use std::thread;",
+        "fn main() {",
+        "    let handle = thread::spawn(|| {});",
+        "    handle.join().unwrap();",
+        "}",
+        "// threads are fun",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect();
+
+    for (index, line) in parallel_grep(lines, "thread", 4) {
+        println!("grep match on line {index}: {line}");
+    }
+}
+
+fn sequential_prefix_sum<T: Copy + Default + Add<Output = T>>(data: &[T]) -> Vec<T> {
+    data.iter()
+        .scan(T::default(), |sum, &value| {
+            *sum = *sum + value;
+            Some(*sum)
+        })
+        .collect()
+}
+
+//An inclusive scan, so element i of the output is the sum of data[0..=i]. Every thread first
+// turns its own chunk into prefix sums as though that chunk were the whole input, which also
+// gives it the chunk's total. The totals of all the chunks before a chunk are exactly what its
+// local sums are missing, so after a barrier, once every total is known, each thread adds up the
+// totals before it and adds that offset to its own chunk. The totals are OnceLocks because each
+// one is written by a single thread exactly once and then only ever read. T::default() stands in
+// for zero, which holds for all of the integer and float types.
+pub fn parallel_prefix_sum<T>(data: &[T], workers: usize) -> Vec<T>
+where
+    T: Copy + Default + Add<Output = T> + Send + Sync,
+{
+    let mut output = data.to_vec();
+    if output.is_empty() {
+        return output;
+    }
+
+    let chunk_size = output.len().div_ceil(workers.max(1));
+    let chunks = output.len().div_ceil(chunk_size);
+    let totals: Vec<OnceLock<T>> = (0..chunks).map(|_| OnceLock::new()).collect();
+    let barrier = Barrier::new(chunks);
+
+    thread::scope(|s| {
+        for (index, chunk) in output.chunks_mut(chunk_size).enumerate() {
+            let (totals, barrier) = (&totals, &barrier);
+            s.spawn(move || {
+                for i in 1..chunk.len() {
+                    chunk[i] = chunk[i] + chunk[i - 1];
+                }
+                let _ = totals[index].set(chunk[chunk.len() - 1]);
+
+                barrier.wait();
+
+                let offset = totals[..index]
+                    .iter()
+                    .map(|total| *total.get().expect("Every total is set before the barrier"))
+                    .fold(T::default(), |sum, total| sum + total);
+                if index > 0 {
+                    chunk.iter_mut().for_each(|value| *value = *value + offset);
+                }
+            });
+        }
+    });
+
+    output
+}
+
+pub fn scanning_prefix_sums() {
+    println!("prefix sums of 1 to 8: {:?}", parallel_prefix_sum(&[1, 2, 3, 4, 5, 6, 7, 8], 3));
+
+    let mut rng = SplittableRng::new(5);
+    for len in [0, 1, 7, 100, 1_001] {
+        let signed: Vec<i64> = (0..len).map(|_| (rng.next_u64() % 200) as i64 - 100).collect();
+        let unsigned: Vec<u64> = (0..len).map(|_| rng.next_u64() % 1_000).collect();
+        println!(
+            "prefix sums of {len} values over 4 threads match sequential: i64 {}, u64 {}",
+            parallel_prefix_sum(&signed, 4) == sequential_prefix_sum(&signed),
+            parallel_prefix_sum(&unsigned, 4) == sequential_prefix_sum(&unsigned)
+        );
+    }
+}
+
+//Every thread dedups its own chunk into a local HashSet with no locking, then the partial sets
+// are merged on the calling thread. A value can show up in several partial sets, which is why the
+// merge is into another set and not just a concatenation. The output comes straight out of a
+// HashSet, so its order is unspecified and can change from run to run.
+pub fn parallel_dedup(items: Vec<u64>, threads: usize) -> Vec<u64> {
+    let partials: Vec<HashSet<u64>> = thread::scope(|s| {
+        let handles: Vec<_> = split_into_chunks(items, threads)
+            .into_iter()
+            .map(|chunk| s.spawn(move || chunk.into_iter().collect::<HashSet<u64>>()))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Dedup thread crashed"))
+            .collect()
+    });
+
+    let mut merged = HashSet::new();
+    for partial in partials {
+        merged.extend(partial);
+    }
+    merged.into_iter().collect()
+}
+
+pub fn deduplicating_in_parallel() {
+    let items: Vec<u64> = (0..10_000).map(|i| (i * i) % 97).collect();
+    let distinct: HashSet<u64> = items.iter().copied().collect();
+
+    let deduped = parallel_dedup(items, 4);
+    let matches = deduped.iter().copied().collect::<HashSet<u64>>() == distinct
+        && deduped.len() == distinct.len();
+    println!(
+        "dedup of 10,000 squares mod 97: {} distinct values, matches the input set: {matches}",
+        deduped.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parallel_dot_matches_sequential() {
+        let a: Vec<f64> = (0..100_000).map(|i| 1.0 / (i as f64 + 1.0)).collect();
+        let b: Vec<f64> = (0..100_000).map(|i| (i % 7) as f64 + 0.1).collect();
+        let sequential: f64 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+
+        for threads in [1, 3, 4, 8] {
+            let parallel = parallel_dot(&a, &b, threads).unwrap();
+            assert!((sequential - parallel).abs() < 1e-9, "{sequential} vs {parallel}");
+        }
+        assert_eq!(parallel_dot(&[], &[], 4).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn parallel_dot_rejects_mismatched_lengths() {
+        let mismatch = parallel_dot(&[1.0, 2.0, 3.0], &[1.0], 2).unwrap_err();
+        assert_eq!((mismatch.left, mismatch.right), (3, 1));
+    }
+
+    #[test]
+    fn parallel_translate_matches_sequential() {
+        let table: HashMap<u32, String> = (0..50).map(|key| (key, format!("word {key}"))).collect();
+        let inputs: Vec<u32> = (0..200).map(|i| (i * 7) % 60).collect();
+
+        let sequential: Vec<String> = inputs
+            .iter()
+            .map(|input| table.get(input).cloned().unwrap_or(MISSING_TRANSLATION.to_string()))
+            .collect();
+
+        assert_eq!(parallel_translate(inputs, table, 3), sequential);
+    }
+
+    #[test]
+    fn parallel_nearest_matches_brute_force() {
+        let mut rng = SplittableRng::new(42);
+        let mut coordinate = || (rng.next_u64() % 10_000) as f64 / 10.0;
+        let points: Vec<(f64, f64)> = (0..2000).map(|_| (coordinate(), coordinate())).collect();
+
+        for target in [(0.0, 0.0), (500.0, 500.0), (123.4, 987.6), (-50.0, 2000.0)] {
+            //Compare distances rather than points, two points can be equally close.
+            let brute_force = points
+                .iter()
+                .map(|point| distance_squared(*point, target))
+                .fold(f64::INFINITY, f64::min);
+            let nearest = parallel_nearest(&points, target, 4).unwrap();
+            assert_eq!(distance_squared(nearest, target), brute_force);
+        }
+        assert_eq!(parallel_nearest(&[], (0.0, 0.0), 4), None);
+        assert_eq!(parallel_nearest(&[(1.0, 2.0)], (9.0, 9.0), 4), Some((1.0, 2.0)));
+    }
+
+    #[test]
+    fn parallel_stencil_matches_sequential() {
+        let data: Vec<f64> = (0..23).map(|i| if i % 5 == 0 { 10.0 } else { i as f64 }).collect();
+
+        for iterations in [0, 1, 2, 5] {
+            let sequential = sequential_stencil(data.clone(), iterations);
+            //4 divides unevenly into 23, and 30 asks for more threads than there are cells.
+            for threads in [1, 4, 6, 30] {
+                assert_eq!(parallel_stencil(data.clone(), iterations, threads), sequential);
+            }
+        }
+        assert!(parallel_stencil(Vec::new(), 3, 4).is_empty());
+    }
+
+    #[test]
+    fn parallel_parse_rows_keeps_rows_and_marks_bad_ones() {
+        let rows: Vec<String> = ["name, age", "alice, 30", "bob,,berlin", "", "carol, 41", "dave,"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let parsed = parallel_parse_rows(rows, 4);
+
+        let expected: Vec<Vec<&str>> = vec![
+            vec!["name", "age"],
+            vec!["alice", "30"],
+            vec![],
+            vec![],
+            vec!["carol", "41"],
+            vec![],
+        ];
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn parallel_gcd_matches_sequential() {
+        let cases: Vec<Vec<u64>> = vec![
+            (1..=1000).map(|i| i * 84).collect(),
+            vec![9, 28, 25, 121, 13],
+            vec![0, 0, 0],
+            vec![0, 12, 0, 18],
+            vec![17],
+            vec![],
+        ];
+
+        for values in cases {
+            let sequential = values.iter().copied().fold(0, gcd);
+            assert_eq!(parallel_gcd(values.clone(), 3), sequential, "{values:?}");
+        }
+        assert_eq!(parallel_gcd(vec![0, 12, 0, 18], 4), 6);
+        assert_eq!(parallel_gcd(vec![], 4), 0);
+    }
+
+    #[test]
+    fn parallel_radix_pass_matches_a_sequential_count() {
+        let values: Vec<u32> = (0..5_001u32).map(|i| i.wrapping_mul(2_654_435_761)).collect();
+        for shift in [0, 8, 16, 24] {
+            let mut expected = [0usize; 256];
+            for value in &values {
+                expected[((value >> shift) & 0xff) as usize] += 1;
+            }
+            for threads in [1, 3, 8] {
+                assert_eq!(parallel_radix_pass(&values, shift, threads), expected);
+            }
+        }
+
+        let known = parallel_radix_pass(&[0x0100, 0x0101, 0x02ff, 0x0001], 8, 2);
+        assert_eq!((known[0], known[1], known[2]), (1, 2, 1));
+        assert_eq!(parallel_radix_pass(&[], 0, 4), [0; 256]);
+    }
+
+    #[test]
+    fn parallel_sort_matches_sort_with_many_duplicates() {
+        let mut rng = SplittableRng::new(7);
+        for (len, distinct) in [(0, 1), (1, 1), (2_000, 5), (10_000, 50), (5_000, 1_000_000)] {
+            let data: Vec<u64> = (0..len).map(|_| rng.next_u64() % distinct).collect();
+            let mut expected = data.clone();
+            expected.sort();
+
+            for cutoff in [0, 16, 1_000] {
+                let mut sorted = data.clone();
+                parallel_sort(&mut sorted, cutoff);
+                assert_eq!(sorted, expected, "len {len}, {distinct} distinct, cutoff {cutoff}");
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_intersection_matches_sequential() {
+        assert_eq!(
+            parallel_intersection(vec![1, 2, 3, 4, 4, 5, 6], vec![4, 6, 8, 6, 2], 3),
+            vec![2, 4, 4, 6]
+        );
+        assert!(parallel_intersection((0..100).collect(), (100..200).collect(), 4).is_empty());
+        assert!(parallel_intersection(Vec::new(), vec![1, 2], 4).is_empty());
+
+        let mut rng = SplittableRng::new(11);
+        let a: Vec<i32> = (0..3_000).map(|_| (rng.next_u64() % 500) as i32).collect();
+        let b: Vec<i32> = (0..800).map(|_| (rng.next_u64() % 1_000) as i32).collect();
+        let expected: Vec<i32> = a.iter().copied().filter(|value| b.contains(value)).collect();
+        for threads in [1, 4, 7] {
+            assert_eq!(parallel_intersection(a.clone(), b.clone(), threads), expected);
+        }
+    }
+
+    #[test]
+    fn parallel_min_max_matches_sequential() {
+        assert_eq!(parallel_min_max(&[], 4), None);
+        assert_eq!(parallel_min_max(&[-3], 4), Some((-3, -3)));
+        assert_eq!(parallel_min_max(&[8; 100], 4), Some((8, 8)));
+
+        let mut rng = SplittableRng::new(99);
+        let random: Vec<i32> = (0..10_001).map(|_| rng.next_u64() as i32).collect();
+        let expected = (*random.iter().min().unwrap(), *random.iter().max().unwrap());
+        for threads in [0, 1, 3, 16] {
+            assert_eq!(parallel_min_max(&random, threads), Some(expected));
+        }
+    }
+
+    #[test]
+    fn parallel_process_lines_keeps_line_order() {
+        let text: String = (0..101).map(|i| format!("line {i}\n")).collect();
+        let expected: Vec<String> = (0..101).map(|i| format!("LINE {i}")).collect();
+        for workers in [1, 3, 7, 200] {
+            assert_eq!(parallel_process_lines(&text, workers, str::to_uppercase), expected);
+        }
+
+        let lengths = parallel_process_lines("a\nbb\n\ncccc", 3, |line| line.len().to_string());
+        assert_eq!(lengths, ["1", "2", "0", "4"]);
+        assert!(parallel_process_lines("", 4, str::to_uppercase).is_empty());
+    }
+
+    #[test]
+    fn parallel_grep_finds_matching_lines_with_their_numbers() {
+        let lines: Vec<String> = [
+            "use std::thread;",
+            "fn main() {",
+            "    let handle = thread::spawn(|| {});",
+            "    handle.join().unwrap();",
+            "}",
+            "// threads are fun",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+        let expected = vec![
+            (0, String::from("use std::thread;")),
+            (2, String::from("    let handle = thread::spawn(|| {});")),
+            (5, String::from("// threads are fun")),
+        ];
+        for threads in [1, 4, 10] {
+            assert_eq!(parallel_grep(lines.clone(), "thread", threads), expected);
+        }
+        assert!(parallel_grep(lines, "missing", 3).is_empty());
+    }
+
+    #[test]
+    fn parallel_prefix_sum_matches_sequential_on_uneven_lengths() {
+        let small = parallel_prefix_sum(&[1i64, 2, 3, 4, 5, 6, 7, 8], 3);
+        assert_eq!(small, [1, 3, 6, 10, 15, 21, 28, 36]);
+
+        let mut rng = SplittableRng::new(5);
+        for len in [1, 2, 7, 100, 1_001, 4_099] {
+            let data: Vec<i64> = (0..len).map(|_| (rng.next_u64() % 200) as i64 - 100).collect();
+            let expected = sequential_prefix_sum(&data);
+            for workers in [1, 3, 4, 8, 64] {
+                assert_eq!(parallel_prefix_sum(&data, workers), expected, "{len} over {workers}");
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_prefix_sum_of_u64_matches_sequential() {
+        assert!(parallel_prefix_sum::<u64>(&[], 4).is_empty());
+        assert_eq!(parallel_prefix_sum(&[7u64], 4), [7]);
+
+        let mut rng = SplittableRng::new(17);
+        let data: Vec<u64> = (0..10_007).map(|_| rng.next_u64() % 1_000).collect();
+        let expected = sequential_prefix_sum(&data);
+        assert_eq!(expected[data.len() - 1], data.iter().sum::<u64>());
+        for workers in [0, 1, 6, 32] {
+            assert_eq!(parallel_prefix_sum(&data, workers), expected, "{workers} workers");
+        }
+    }
+
+    #[test]
+    fn parallel_dedup_matches_a_sequential_hash_set() {
+        let mut rng = SplittableRng::new(3);
+        for (len, distinct) in [(0, 1), (1, 1), (10_000, 97), (5_001, 1_000_000)] {
+            let items: Vec<u64> = (0..len).map(|_| rng.next_u64() % distinct).collect();
+            let expected: HashSet<u64> = items.iter().copied().collect();
+
+            for threads in [1, 4, 9] {
+                let deduped = parallel_dedup(items.clone(), threads);
+                //As many values as the set has, so none of them came out twice.
+                assert_eq!(deduped.len(), expected.len());
+                assert_eq!(deduped.into_iter().collect::<HashSet<u64>>(), expected);
+            }
+        }
+    }
+}
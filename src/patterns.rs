@@ -0,0 +1,124 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, mpsc, Mutex};
+use std::thread;
+
+//Double buffering. The producer draws the next frame into the back buffer while the consumer is
+// reading the front buffer, and the two only meet for the brief moment when the buffers are
+// swapped. Each buffer has its own lock, so drawing and reading never wait on each other. Two
+// channels make the hand off explicit, `ready` says a new frame was swapped to the front and
+// `displayed` says the consumer is done with it so the next swap won't pull it away mid read.
+// Frame n is filled with the value n, and the consumer returns the value of every frame it read.
+// A torn frame, half one frame and half the next, would have cells that disagree, so the
+// consumer checks every cell rather than trusting the first one.
+pub fn double_buffer_demo(frames: usize) -> Vec<i32> {
+    const FRAME_SIZE: usize = 4;
+
+    let front = Arc::new(Mutex::new(vec![0; FRAME_SIZE]));
+    let back = Arc::new(Mutex::new(vec![0; FRAME_SIZE]));
+    let (ready_tx, ready_rx) = mpsc::channel::<()>();
+    let (displayed_tx, displayed_rx) = mpsc::channel::<()>();
+
+    let producer = {
+        let front = Arc::clone(&front);
+        let back = Arc::clone(&back);
+        thread::spawn(move || {
+            for frame in 0..frames as i32 {
+                back.lock().unwrap().fill(frame);
+
+                //The first swap can happen right away, after that the consumer has to be done
+                // with the current front buffer.
+                if frame > 0 && displayed_rx.recv().is_err() {
+                    return;
+                }
+
+                let mut front = front.lock().unwrap();
+                let mut back = back.lock().unwrap();
+                std::mem::swap(&mut *front, &mut *back);
+                drop((front, back));
+
+                ready_tx.send(()).expect("Consumer hung up");
+            }
+        })
+    };
+
+    let mut seen = Vec::with_capacity(frames);
+    for _ in ready_rx {
+        let frame = front.lock().unwrap();
+        let value = frame[0];
+        assert!(frame.iter().all(|&cell| cell == value), "Read a torn frame: {:?}", *frame);
+        seen.push(value);
+        drop(frame);
+        //The producer may have already quit after its last frame, that's fine.
+        let _ = displayed_tx.send(());
+    }
+
+    producer.join().expect("Producer crashed");
+    seen
+}
+
+pub fn swapping_double_buffers() {
+    println!("frames seen through the double buffer: {:?}", double_buffer_demo(6));
+}
+
+//Publishing data through a flag. The producer writes the payload and then sets `ready` with
+// Release. The consumer spins until it sees `ready` with Acquire and only then reads the payload.
+// A Release store and an Acquire load that sees it pair up: everything the producer wrote before
+// the store is guaranteed to be visible to the consumer after the load. The payload itself is
+// written and read with Relaxed, since the flag is what does the synchronizing. With Relaxed on
+// the flag too, the CPU or the compiler would be free to make `ready` visible before the payload,
+// and the consumer could read the stale 0s. x86 happens to never do that for plain stores, ARM
+// does. Returns the two halves of the payload as the consumer saw them.
+pub fn producer_consumer_with_release_acquire() -> (u64, u64) {
+    let first = AtomicU64::new(0);
+    let second = AtomicU64::new(0);
+    let ready = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            first.store(42, Ordering::Relaxed);
+            second.store(43, Ordering::Relaxed);
+            ready.store(true, Ordering::Release);
+        });
+
+        let consumer = s.spawn(|| {
+            while !ready.load(Ordering::Acquire) {
+                std::hint::spin_loop();
+            }
+            (first.load(Ordering::Relaxed), second.load(Ordering::Relaxed))
+        });
+
+        consumer.join().expect("Consumer crashed")
+    })
+}
+
+pub fn publishing_with_release_and_acquire() {
+    //Loom would check every allowed interleaving and reordering, but it isn't available here,
+    // so this just runs the handoff many times. The guarantee means stale reads should never
+    // show up, on any CPU.
+    let rounds = 2_000;
+    let stale = (0..rounds)
+        .map(|_| producer_consumer_with_release_acquire())
+        .filter(|&payload| payload != (42, 43))
+        .count();
+    println!("release/acquire handoff: {stale} stale reads in {rounds} rounds");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consumer_sees_every_frame_whole_and_in_order() {
+        for frames in [1, 6, 200] {
+            assert_eq!(double_buffer_demo(frames), (0..frames as i32).collect::<Vec<_>>());
+        }
+        assert!(double_buffer_demo(0).is_empty());
+    }
+
+    #[test]
+    fn consumer_never_sees_ready_with_stale_data() {
+        for round in 0..2_000 {
+            assert_eq!(producer_consumer_with_release_acquire(), (42, 43), "round {round}");
+        }
+    }
+}
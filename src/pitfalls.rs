@@ -0,0 +1,397 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//Holding a MutexGuard while doing slow work (sleeping here, but it could just as well be I/O)
+// means every other thread that wants the lock sits there doing nothing. In async code this is
+// the "guard held across an .await" problem, but it is just as much of a problem with threads.
+// Each thread updates the shared value and then does some slow work, and the total time threads
+// spent waiting on lock() is returned. With `hold_during_sleep` the slow work happens while the
+// guard is still alive, otherwise the guard is dropped first.
+pub fn long_held_lock_demo(hold_during_sleep: bool) -> Duration {
+    let shared = Arc::new(Mutex::new(0));
+    let blocked = Arc::new(Mutex::new(Duration::ZERO));
+
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let shared = Arc::clone(&shared);
+            let blocked = Arc::clone(&blocked);
+            thread::spawn(move || {
+                let started_waiting = Instant::now();
+                let mut value = shared.lock().unwrap();
+                *blocked.lock().unwrap() += started_waiting.elapsed();
+
+                *value += 1;
+
+                //The critical section really ends here, everything after this line doesn't
+                // touch the shared value.
+                if !hold_during_sleep {
+                    drop(value);
+                }
+
+                thread::sleep(Duration::from_millis(20));
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().expect("Lock holder crashed");
+    }
+
+    let total = *blocked.lock().unwrap();
+    total
+}
+
+pub fn holding_a_lock_too_long() {
+    println!(
+        "time blocked with the lock held during sleep: {:?}",
+        long_held_lock_demo(true)
+    );
+    println!(
+        "time blocked with the lock released before sleep: {:?}",
+        long_held_lock_demo(false)
+    );
+}
+
+//println!() locks stdout for the whole call, so a single println!() never gets split up. Lines do
+// get mangled as soon as one line is written with several print!() calls, because another thread
+// can grab stdout in between them. That is what this does, each thread writes every line in three
+// pieces.
+pub fn noisy_output(threads: usize, lines: usize) {
+    thread::scope(|s| {
+        for id in 0..threads {
+            s.spawn(move || {
+                for line in 0..lines {
+                    print!("thread {id} ");
+                    thread::yield_now();
+                    print!("line {line} ");
+                    thread::yield_now();
+                    println!("done");
+                }
+            });
+        }
+    });
+}
+
+//Same as noisy_output() but every line is written while holding a shared lock. Mutex<()> doesn't
+// protect any data, the lock itself is the only thing that matters.
+pub fn ordered_output(threads: usize, lines: usize) {
+    let output_lock = Mutex::new(());
+
+    thread::scope(|s| {
+        for id in 0..threads {
+            let output_lock = &output_lock;
+            s.spawn(move || {
+                for line in 0..lines {
+                    let _guard = output_lock.lock().unwrap();
+                    print!("thread {id} ");
+                    thread::yield_now();
+                    print!("line {line} ");
+                    thread::yield_now();
+                    println!("done");
+                }
+            });
+        }
+    });
+}
+
+//ordered_output() writing into a Vec<String> instead of stdout so the lines can be checked. Each
+// line is built from the same three pieces while the sink is locked.
+pub fn ordered_output_to_sink(threads: usize, lines: usize) -> Arc<Mutex<Vec<String>>> {
+    let sink = Arc::new(Mutex::new(Vec::new()));
+
+    thread::scope(|s| {
+        for id in 0..threads {
+            let sink = Arc::clone(&sink);
+            s.spawn(move || {
+                for line in 0..lines {
+                    let mut sink = sink.lock().unwrap();
+                    sink.push(format!("thread {id} "));
+                    let last = sink.last_mut().expect("Just pushed");
+                    last.push_str(&format!("line {line} "));
+                    last.push_str("done");
+                }
+            });
+        }
+    });
+
+    sink
+}
+
+pub fn interleaving_output() {
+    println!("unsynchronized output:");
+    noisy_output(3, 2);
+    println!("synchronized output:");
+    ordered_output(3, 2);
+
+    let sink = ordered_output_to_sink(4, 25);
+    let sink = sink.lock().unwrap();
+    let complete = sink
+        .iter()
+        .all(|line| line.starts_with("thread ") && line.ends_with(" done"));
+    println!("synchronized sink has {} lines, all complete: {complete}", sink.len());
+}
+
+//Arc<Mutex<T>> and Mutex<Arc<T>> look similar but mean very different things.
+// 1) Arc<Mutex<i32>>; one shared value that every thread can change. The Arc<T> is what gets
+//  cloned and handed to each thread, and the lock is what makes changing the value safe. This
+//  returns the value after every thread added 1 to it, so 4.
+// 2) Mutex<Arc<i32>>; the value behind the Arc<T> is immutable, what the mutex protects is which
+//  Arc<T> is the current one. A thread can swap in a whole new Arc<T>, and anybody that cloned the
+//  old one before the swap keeps seeing the old value. This returns the swapped in value, 42.
+pub fn nesting_demo() -> (i32, i32) {
+    let shared_mutable = Arc::new(Mutex::new(0));
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let shared_mutable = Arc::clone(&shared_mutable);
+            thread::spawn(move || *shared_mutable.lock().unwrap() += 1)
+        })
+        .collect();
+    for handle in handles {
+        handle.join().expect("Incrementing thread crashed");
+    }
+    let mutated = *shared_mutable.lock().unwrap();
+
+    let swappable = Mutex::new(Arc::new(1));
+    let before_swap = Arc::clone(&swappable.lock().unwrap());
+    thread::scope(|s| {
+        s.spawn(|| *swappable.lock().unwrap() = Arc::new(42));
+    });
+    let swapped_in = **swappable.lock().unwrap();
+    println!("old Arc cloned before the swap still sees: {before_swap}");
+
+    (mutated, swapped_in)
+}
+
+pub fn nesting_arc_and_mutex() {
+    let (mutated, swapped_in) = nesting_demo();
+    println!("Arc<Mutex<i32>> after 4 increments: {mutated}");
+    println!("Mutex<Arc<i32>> after the swap: {swapped_in}");
+}
+
+//A sync_channel(0) is a rendezvous channel, send() blocks until another thread is in recv() at
+// the same moment. So a single thread that sends and then receives on its own rendezvous channel
+// waits forever, nobody else will ever receive. With room in the buffer the send() returns right
+// away and the thread gets to its own recv(). The watchdog is the main thread waiting on a second
+// channel with a timeout. A deadlocked worker can't be killed, so it is left behind, blocked for
+// the rest of the program.
+pub fn capacity_deadlock_demo(capacity: usize) -> Result<(), &'static str> {
+    let (done_tx, done_rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        tx.send("ping").expect("Receiver is in this same thread");
+        let message = rx.recv().expect("Sender is in this same thread");
+        let _ = done_tx.send(message);
+    });
+
+    match done_rx.recv_timeout(Duration::from_millis(100)) {
+        Ok(_) => Ok(()),
+        Err(_) => Err("send() on the channel never returned"),
+    }
+}
+
+pub fn channel_capacity_and_deadlock() {
+    println!("rendezvous channel (capacity 0): {:?}", capacity_deadlock_demo(0));
+    println!("buffered channel (capacity 1): {:?}", capacity_deadlock_demo(1));
+}
+
+//If a thread panics while it holds a MutexGuard, the guard's drop marks the mutex as poisoned,
+// the data might have been left half updated. Every lock() after that returns Err(PoisonError)
+// instead of the guard, which is why the .unwrap() on lock() that is everywhere in this repo would
+// panic too. The PoisonError still holds the guard (into_inner()) for code that wants to carry on
+// anyway. Returns whether the main thread saw the mutex as poisoned.
+pub fn poisoning_demo() -> bool {
+    let shared = Arc::new(Mutex::new(0));
+
+    let panicking = {
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            let mut value = shared.lock().unwrap();
+            *value += 1;
+            panic!("panicked while holding the lock");
+        })
+    };
+    assert!(panicking.join().is_err(), "The thread was supposed to panic");
+
+    let poisoned = match shared.lock() {
+        Ok(_) => false,
+        Err(poison) => {
+            println!("value left behind in the poisoned mutex: {}", *poison.into_inner());
+            true
+        }
+    };
+    poisoned
+}
+
+pub fn poisoning_a_mutex() {
+    println!("lock() after a panic returned a PoisonError: {}", poisoning_demo());
+}
+
+//std's Mutex makes no promise about fairness. On unlock it doesn't hand the lock to whoever has
+// been waiting longest, whichever thread calls lock() next wins. A thread that unlocks and
+// immediately locks again is almost always that thread, it is already running while the waiter
+// still has to be woken up. So the greedy thread gets the lock over and over and the polite one
+// only sneaks in now and then. Returns (greedy acquisitions, polite acquisitions). The polite
+// thread gives up once the greedy one is done.
+pub fn starvation_demo(greedy_iters: usize) -> (usize, usize) {
+    let lock = Mutex::new(0u64);
+    let greedy_done = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        let greedy = s.spawn(|| {
+            for _ in 0..greedy_iters {
+                let mut value = lock.lock().unwrap();
+                //A bit of work with the lock held so the polite thread has time to start waiting.
+                for i in 0..100 {
+                    *value = value.wrapping_add(i);
+                }
+            }
+            greedy_done.store(true, Ordering::SeqCst);
+            greedy_iters
+        });
+
+        let polite = s.spawn(|| {
+            let mut acquired = 0;
+            while !greedy_done.load(Ordering::SeqCst) {
+                drop(lock.lock().unwrap());
+                acquired += 1;
+                //Politely stepping aside after every turn.
+                thread::yield_now();
+            }
+            acquired
+        });
+
+        (
+            greedy.join().expect("Greedy thread crashed"),
+            polite.join().expect("Polite thread crashed"),
+        )
+    })
+}
+
+pub fn starving_a_polite_thread() {
+    let (greedy, polite) = starvation_demo(200_000);
+    println!(
+        "greedy thread locked {greedy} times, polite thread {polite} times, \
+         greedy got it far more often: {}",
+        greedy > polite * 5
+    );
+}
+
+//Locking a Mutex this thread already holds is not a panic, the docs leave the exact behavior
+// unspecified and only promise that the second lock() never returns. On Linux it just blocks
+// forever, waiting for a guard that can only be dropped by the thread that is stuck waiting.
+// Same watchdog idea as capacity_deadlock_demo(), and the stuck thread is left behind the same
+// way.
+pub fn double_lock_demo() -> Result<(), &'static str> {
+    let (done_tx, done_rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let lock = Mutex::new(0);
+        let first = lock.lock().unwrap();
+        let second = lock.lock().unwrap();
+        let _ = done_tx.send(*first + *second);
+    });
+
+    match done_rx.recv_timeout(Duration::from_millis(100)) {
+        Ok(_) => Ok(()),
+        Err(_) => Err("the second lock() on the same thread never returned"),
+    }
+}
+
+pub fn locking_a_mutex_twice() {
+    println!("locking the same mutex twice: {:?}", double_lock_demo());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::TryLockError;
+
+    use super::*;
+
+    #[test]
+    fn holding_the_lock_during_sleep_blocks_everyone_else() {
+        let held = long_held_lock_demo(true);
+        let released = long_held_lock_demo(false);
+
+        //With the guard held, at least one thread has to sit out somebody's whole 20ms sleep.
+        assert!(held >= Duration::from_millis(20), "held: {held:?}");
+        assert!(held > released, "held: {held:?}, released: {released:?}");
+    }
+
+    #[test]
+    fn ordered_sink_only_holds_whole_lines() {
+        let sink = ordered_output_to_sink(4, 25);
+        let sink = sink.lock().unwrap();
+        assert_eq!(sink.len(), 100);
+
+        //Each thread's lines are complete and in the order that thread wrote them.
+        for id in 0..4 {
+            let prefix = format!("thread {id} ");
+            let lines: Vec<&String> =
+                sink.iter().filter(|line| line.starts_with(&prefix)).collect();
+            let expected: Vec<String> =
+                (0..25).map(|line| format!("thread {id} line {line} done")).collect();
+            assert_eq!(lines, expected.iter().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn arc_mutex_is_shared_and_mutex_arc_is_swapped() {
+        assert_eq!(nesting_demo(), (4, 42));
+    }
+
+    #[test]
+    fn rendezvous_channel_blocks_a_lone_thread() {
+        assert!(capacity_deadlock_demo(0).is_err());
+        assert_eq!(capacity_deadlock_demo(1), Ok(()));
+    }
+
+    #[test]
+    fn panicking_while_locked_poisons_the_mutex() {
+        assert!(poisoning_demo());
+
+        let shared = Arc::new(Mutex::new(0));
+        let panicking = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                let mut value = shared.lock().unwrap();
+                *value += 1;
+                panic!("panicked while holding the lock");
+            })
+        };
+        assert!(panicking.join().is_err());
+
+        assert!(shared.is_poisoned());
+        let recovered = match shared.lock() {
+            Ok(_) => panic!("lock() should fail after a panic"),
+            Err(poison) => *poison.into_inner(),
+        };
+        assert_eq!(recovered, 1);
+        //Poisoning sticks around, recovering the value once doesn't clear it.
+        assert!(shared.lock().is_err());
+    }
+
+    #[test]
+    fn greedy_thread_starves_the_polite_one() {
+        let (greedy, polite) = starvation_demo(200_000);
+        assert_eq!(greedy, 200_000);
+        assert!(greedy > polite * 5, "greedy {greedy}, polite {polite}");
+    }
+
+    #[test]
+    fn try_lock_fails_while_this_thread_holds_the_guard() {
+        let lock = Mutex::new(0);
+        let guard = lock.lock().unwrap();
+        assert!(matches!(lock.try_lock(), Err(TryLockError::WouldBlock)));
+
+        drop(guard);
+        assert_eq!(*lock.try_lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn second_lock_on_the_same_thread_never_returns() {
+        assert!(double_lock_demo().is_err());
+    }
+}
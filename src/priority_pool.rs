@@ -0,0 +1,300 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Condvar, mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::thread_pool::Job;
+
+//A job along with what it takes to order it in the heap. BinaryHeap pops the largest item first,
+// so higher priorities come out first. Between equal priorities the lower sequence number wins,
+// which keeps jobs of the same priority in the order they were submitted.
+struct PrioritizedJob {
+    priority: u8,
+    sequence: u64,
+    job: Job,
+}
+
+impl PartialEq for PrioritizedJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PrioritizedJob {}
+
+impl PartialOrd for PrioritizedJob {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PrioritizedJob {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+struct Queue {
+    jobs: BinaryHeap<PrioritizedJob>,
+    next_sequence: u64,
+    shutting_down: bool,
+}
+
+//The ThreadPool hands jobs out in the order they were sent because it uses a channel. To pick
+// the most important job instead, the queue here is a BinaryHeap behind a Mutex<T>, with a
+// Condvar for the workers to sleep on while it is empty.
+pub struct PriorityPool {
+    shared: Arc<(Mutex<Queue>, Condvar)>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl PriorityPool {
+    pub fn new(size: usize) -> PriorityPool {
+        assert!(size > 0, "A thread pool needs at least one worker");
+
+        let shared = Arc::new((
+            Mutex::new(Queue {
+                jobs: BinaryHeap::new(),
+                next_sequence: 0,
+                shutting_down: false,
+            }),
+            Condvar::new(),
+        ));
+
+        let workers = (0..size)
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || {
+                    let (queue, available) = &*shared;
+                    loop {
+                        let queue = queue.lock().unwrap();
+                        let mut queue = available
+                            .wait_while(queue, |queue| {
+                                queue.jobs.is_empty() && !queue.shutting_down
+                            })
+                            .unwrap();
+
+                        //Only exits once the queue is empty, so everything that was submitted
+                        // still runs when the pool is dropped.
+                        let Some(next) = queue.jobs.pop() else {
+                            break;
+                        };
+                        drop(queue);
+
+                        (next.job)();
+                    }
+                })
+            })
+            .collect();
+
+        PriorityPool { shared, workers }
+    }
+
+    pub fn execute(&self, priority: u8, job: Job) {
+        let (queue, available) = &*self.shared;
+        let mut queue = queue.lock().unwrap();
+
+        let sequence = queue.next_sequence;
+        queue.next_sequence += 1;
+        queue.jobs.push(PrioritizedJob {
+            priority,
+            sequence,
+            job,
+        });
+
+        available.notify_one();
+    }
+}
+
+impl Drop for PriorityPool {
+    fn drop(&mut self) {
+        let (queue, available) = &*self.shared;
+        queue.lock().unwrap().shutting_down = true;
+        available.notify_all();
+
+        for worker in self.workers.drain(..) {
+            worker.join().expect("Priority worker crashed");
+        }
+    }
+}
+
+pub fn running_high_priority_jobs_first() {
+    let pool = PriorityPool::new(1);
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    //Keeps the only worker busy until everything else has been queued up, that way the order the
+    // rest of the jobs run in comes down to their priorities alone.
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    pool.execute(0, Box::new(move || {
+        let _ = release_rx.recv();
+    }));
+
+    for i in 0..4 {
+        let order = Arc::clone(&order);
+        pool.execute(1, Box::new(move || order.lock().unwrap().push(format!("low {i}"))));
+    }
+    let high_order = Arc::clone(&order);
+    pool.execute(9, Box::new(move || high_order.lock().unwrap().push(String::from("high"))));
+
+    drop(release_tx);
+    drop(pool);
+
+    println!("priority pool run order: {:?}", order.lock().unwrap());
+}
+
+//A plain priority queue lets a steady stream of important items keep an unimportant one waiting
+// forever. Here an item's effective priority goes up by one for every `aging_interval` it has
+// been waiting, so given enough time anything ends up outranking newly added items. Effective
+// priorities keep changing as time passes, which a BinaryHeap can't cope with, so pop() just
+// scans every pending item. Between equal effective priorities the older item wins.
+pub struct AgingPriorityQueue<T> {
+    items: Mutex<Vec<AgingItem<T>>>,
+    available: Condvar,
+    aging_interval: Duration,
+}
+
+struct AgingItem<T> {
+    item: T,
+    priority: u8,
+    inserted: Instant,
+}
+
+impl<T> AgingPriorityQueue<T> {
+    pub fn new(aging_interval: Duration) -> AgingPriorityQueue<T> {
+        assert!(!aging_interval.is_zero(), "Items would age infinitely fast");
+
+        AgingPriorityQueue {
+            items: Mutex::new(Vec::new()),
+            available: Condvar::new(),
+            aging_interval,
+        }
+    }
+
+    pub fn push(&self, item: T, priority: u8) {
+        self.items.lock().unwrap().push(AgingItem {
+            item,
+            priority,
+            inserted: Instant::now(),
+        });
+        self.available.notify_one();
+    }
+
+    fn effective_priority(&self, item: &AgingItem<T>, now: Instant) -> u128 {
+        let waited = now.duration_since(item.inserted);
+        item.priority as u128 + waited.as_nanos() / self.aging_interval.as_nanos()
+    }
+
+    //Blocks while the queue is empty.
+    pub fn pop(&self) -> T {
+        let items = self.items.lock().unwrap();
+        let mut items = self.available.wait_while(items, |items| items.is_empty()).unwrap();
+
+        let now = Instant::now();
+        let best = items
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| {
+                self.effective_priority(a, now)
+                    .cmp(&self.effective_priority(b, now))
+                    .then_with(|| b.inserted.cmp(&a.inserted))
+            })
+            .map(|(index, _)| index)
+            .expect("The queue isn't empty");
+
+        //Order between items is decided by their effective priority, so swap_remove() messing
+        // up the order of the Vec doesn't matter.
+        items.swap_remove(best).item
+    }
+}
+
+pub fn aging_low_priority_items() {
+    let queue = AgingPriorityQueue::new(Duration::from_millis(10));
+    queue.push(String::from("low priority, added first"), 0);
+
+    //Fresh priority 3 items keep arriving. Without aging the low priority item would never be
+    // picked, with aging it outranks them once it has waited a bit over 30ms.
+    let mut served = Vec::new();
+    for round in 0..20 {
+        queue.push(format!("high priority {round}"), 3);
+        queue.push(format!("high priority {round}b"), 3);
+        let item = queue.pop();
+        let done = item.starts_with("low");
+        served.push(item);
+        if done {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    println!(
+        "low priority item served after {} high priority items: {:?}",
+        served.len() - 1,
+        served.last()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn higher_priority_jobs_run_first() {
+        let pool = PriorityPool::new(1);
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        pool.execute(0, Box::new(move || {
+            let _ = release_rx.recv();
+        }));
+
+        let jobs = [("low 0", 1), ("mid", 5), ("low 1", 1), ("high", 9), ("low 2", 1)];
+        for (name, priority) in jobs {
+            let order = Arc::clone(&order);
+            pool.execute(priority, Box::new(move || order.lock().unwrap().push(name)));
+        }
+
+        drop(release_tx);
+        drop(pool);
+
+        //Equal priorities keep the order they were submitted in.
+        assert_eq!(*order.lock().unwrap(), vec!["high", "mid", "low 0", "low 1", "low 2"]);
+    }
+
+    #[test]
+    fn low_priority_item_is_served_under_steady_high_priority_load() {
+        let queue = AgingPriorityQueue::new(Duration::from_millis(10));
+        queue.push("low", 0);
+
+        //Two fresh high priority items for every pop, so without aging the backlog of high
+        // items would only grow and the low one would never come up.
+        let mut high_served = 0;
+        let served_low = (0..500).any(|_| {
+            queue.push("high", 3);
+            queue.push("high", 3);
+            if queue.pop() == "low" {
+                return true;
+            }
+            high_served += 1;
+            thread::sleep(Duration::from_millis(2));
+            false
+        });
+
+        assert!(served_low, "The low priority item was never served");
+        assert!(high_served > 0, "A fresh high priority item should win at first");
+    }
+
+    #[test]
+    fn equal_priorities_come_out_oldest_first() {
+        let queue = AgingPriorityQueue::new(Duration::from_secs(60));
+        for item in ["a", "b", "c"] {
+            queue.push(item, 5);
+            thread::sleep(Duration::from_millis(1));
+        }
+        queue.push("urgent", 9);
+        let popped: Vec<&str> = (0..4).map(|_| queue.pop()).collect();
+        assert_eq!(popped, ["urgent", "a", "b", "c"]);
+    }
+}
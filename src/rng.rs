@@ -0,0 +1,155 @@
+use std::thread;
+
+//A tiny SplitMix64 generator. Sharing one generator between threads would need a lock, and even
+// then the numbers each thread gets would depend on how the threads happened to be scheduled. So
+// instead the main thread seeds one generator and split()s off a separate generator for every
+// thread. The split generators are seeded from the parent's output, so as long as the seed and
+// the number of splits are the same, every thread gets exactly the same numbers on every run.
+pub struct SplittableRng {
+    state: u64,
+}
+
+impl SplittableRng {
+    pub fn new(seed: u64) -> SplittableRng {
+        SplittableRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    //Running the parent's output through the generator one more time keeps the child's sequence
+    // from simply being a shifted copy of the parent's sequence.
+    pub fn split(&mut self) -> SplittableRng {
+        let mut child = SplittableRng::new(self.next_u64());
+        SplittableRng::new(child.next_u64())
+    }
+}
+
+fn seeded_parallel_sum(seed: u64, threads: usize) -> u64 {
+    let mut rng = SplittableRng::new(seed);
+
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let mut thread_rng = rng.split();
+            thread::spawn(move || (0..1000).map(|_| thread_rng.next_u64() % 100).sum::<u64>())
+        })
+        .collect();
+
+    handles
+        .into_iter()
+        .map(|handle| handle.join().expect("Random sum thread crashed"))
+        .sum()
+}
+
+pub fn reproducible_parallel_randomness() {
+    let first = seeded_parallel_sum(42, 4);
+    let second = seeded_parallel_sum(42, 4);
+    let other_seed = seeded_parallel_sum(7, 4);
+    println!("seeded parallel sums: {first} and {second} with seed 42, {other_seed} with seed 7");
+}
+
+//Marsaglia's xorshift64, about the smallest generator that is still decent. Its state must never
+// be zero, zero just keeps producing zero.
+fn xorshift64(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+//The top 53 bits as a float in [0, 1), which is all of the precision an f64 can hold.
+fn unit_f64(state: &mut u64) -> f64 {
+    (xorshift64(state) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+//A quarter circle of radius 1 covers pi / 4 of the unit square, so four times the fraction of
+// random points in the square that land inside it approximates pi. Every thread has its own
+// generator seeded from `seed` and its thread number, so there is no shared state to fight over
+// and the same arguments always give the same estimate.
+pub fn estimate_pi(samples: usize, threads: usize, seed: u64) -> f64 {
+    let threads = threads.max(1);
+
+    let inside: usize = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|index| {
+                //The first threads take one extra sample each when samples doesn't divide evenly.
+                let count = samples / threads + usize::from(index < samples % threads);
+                let spread = (index as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                let mut state = (seed ^ spread).max(1);
+
+                s.spawn(move || {
+                    (0..count)
+                        .filter(|_| {
+                            let (x, y) = (unit_f64(&mut state), unit_f64(&mut state));
+                            x * x + y * y < 1.0
+                        })
+                        .count()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Sampling thread crashed"))
+            .sum()
+    });
+
+    4.0 * inside as f64 / samples.max(1) as f64
+}
+
+pub fn estimating_pi() {
+    let estimate = estimate_pi(2_000_000, 4, 12345);
+    println!(
+        "pi estimated from 2,000,000 samples: {estimate:.5}, off by {:.5}, same again: {}",
+        (estimate - std::f64::consts::PI).abs(),
+        estimate == estimate_pi(2_000_000, 4, 12345)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn streams(seed: u64, threads: usize) -> Vec<Vec<u64>> {
+        let mut rng = SplittableRng::new(seed);
+        let children: Vec<SplittableRng> = (0..threads).map(|_| rng.split()).collect();
+
+        let handles: Vec<_> = children
+            .into_iter()
+            .map(|mut child| thread::spawn(move || (0..100).map(|_| child.next_u64()).collect()))
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_streams() {
+        let first = streams(42, 4);
+        assert_eq!(first, streams(42, 4));
+        assert_ne!(first, streams(7, 4));
+
+        //Every thread gets its own sequence, not a copy of somebody else's.
+        for (i, a) in first.iter().enumerate() {
+            for b in &first[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+        assert_eq!(seeded_parallel_sum(42, 4), seeded_parallel_sum(42, 4));
+    }
+
+    #[test]
+    fn estimate_pi_is_close_and_repeatable() {
+        for seed in [1, 12345, u64::MAX] {
+            let estimate = estimate_pi(400_000, 4, seed);
+            assert!((estimate - std::f64::consts::PI).abs() < 0.02, "seed {seed}: {estimate}");
+            assert_eq!(estimate, estimate_pi(400_000, 4, seed));
+        }
+        assert_ne!(estimate_pi(400_000, 4, 1), estimate_pi(400_000, 4, 2));
+
+        assert_eq!(estimate_pi(0, 4, 9), 0.0);
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::thread_pool::{Job, ThreadPool};
+
+//The result of polling a cooperative task once. Pending means the task gave up its turn and wants
+// to be polled again later.
+pub enum Poll<T> {
+    Ready(T),
+    Pending,
+}
+
+//A cooperative task is just a closure that does a little bit of work each time it is called. It
+// is FnMut because it usually needs to remember how far it got between polls.
+pub type Task<T> = Box<dyn FnMut() -> Poll<T> + Send>;
+
+//This is cooperative multitasking on top of the thread pool. No task gets a thread of its own,
+// each poll is a short job on the pool. After every round the tasks that are still pending go to
+// the back of the queue and get polled again, so every task gets a turn. The catch with
+// cooperative scheduling is that a task that never returns from a poll hogs a worker forever,
+// nothing can preempt it.
+pub struct CooperativeScheduler {
+    pool: ThreadPool,
+}
+
+impl CooperativeScheduler {
+    pub fn new(workers: usize) -> CooperativeScheduler {
+        CooperativeScheduler {
+            pool: ThreadPool::new(workers),
+        }
+    }
+
+    //Runs every task to completion and returns the results in the same order as the tasks.
+    pub fn run<T: Send + 'static>(&self, tasks: Vec<Task<T>>) -> Vec<T> {
+        let mut results: Vec<Option<T>> = tasks.iter().map(|_| None).collect();
+        let mut pending: VecDeque<(usize, Task<T>)> = tasks.into_iter().enumerate().collect();
+
+        while !pending.is_empty() {
+            //The task gets moved into the job and handed back along with the poll result, that
+            // way pending tasks can be queued up again for the next round.
+            let handles: Vec<_> = pending
+                .drain(..)
+                .map(|(index, mut task)| {
+                    self.pool.submit(move || {
+                        let poll = task();
+                        (index, task, poll)
+                    })
+                })
+                .collect();
+
+            for handle in handles {
+                match handle.wait() {
+                    (index, _, Poll::Ready(value)) => results[index] = Some(value),
+                    (index, task, Poll::Pending) => pending.push_back((index, task)),
+                }
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("Every task was run until it was ready"))
+            .collect()
+    }
+}
+
+//Builds a task that yields Pending `yields` times before finishing with a message saying how
+// many times it was polled.
+fn countdown_task(name: &'static str, yields: usize) -> Task<String> {
+    let mut polls = 0;
+    Box::new(move || {
+        polls += 1;
+        if polls <= yields {
+            println!("{name} yielding on poll {polls}");
+            Poll::Pending
+        } else {
+            Poll::Ready(format!("{name} was ready after {polls} polls"))
+        }
+    })
+}
+
+pub fn cooperative_scheduling_on_a_pool() {
+    let scheduler = CooperativeScheduler::new(2);
+
+    let results = scheduler.run(vec![countdown_task("task a", 2), countdown_task("task b", 4)]);
+
+    for result in results {
+        println!("{result}");
+    }
+}
+
+//Tasks with dependencies between them. A task only starts once every task it depends on has
+// finished, and anything that is ready runs at the same time as everything else that is ready.
+pub struct DagRunner {
+    tasks: Vec<DagTask>,
+}
+
+struct DagTask {
+    id: String,
+    deps: Vec<String>,
+    job: Job,
+}
+
+#[derive(Debug)]
+pub enum DagError {
+    DuplicateTask(String),
+    UnknownDependency { task: String, dependency: String },
+    //Every task that can never run. That is the tasks on a cycle plus anything that depends on one.
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for DagError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DagError::DuplicateTask(id) => write!(f, "task {id} was added twice"),
+            DagError::UnknownDependency { task, dependency } => {
+                write!(f, "task {task} depends on {dependency}, which doesn't exist")
+            }
+            DagError::Cycle(stuck) => write!(f, "dependency cycle, these can never run: {stuck:?}"),
+        }
+    }
+}
+
+impl DagRunner {
+    pub fn new() -> DagRunner {
+        DagRunner { tasks: Vec::new() }
+    }
+
+    pub fn add_task<F>(&mut self, id: &str, deps: &[&str], f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.tasks.push(DagTask {
+            id: id.to_string(),
+            deps: deps.iter().map(|dep| dep.to_string()).collect(),
+            job: Box::new(f),
+        });
+    }
+
+    //Everything is checked before any task runs, so a bad graph never runs half way. On success
+    // the ids are returned in the order the tasks finished in.
+    pub fn run(self, workers: usize) -> Result<Vec<String>, DagError> {
+        let mut index_of = HashMap::new();
+        for (index, task) in self.tasks.iter().enumerate() {
+            if index_of.insert(task.id.clone(), index).is_some() {
+                return Err(DagError::DuplicateTask(task.id.clone()));
+            }
+        }
+
+        //For each task, how many dependencies it is still waiting on and who is waiting on it.
+        let mut waiting_on = vec![0; self.tasks.len()];
+        let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); self.tasks.len()];
+        for (index, task) in self.tasks.iter().enumerate() {
+            for dep in &task.deps {
+                let Some(&dep_index) = index_of.get(dep) else {
+                    return Err(DagError::UnknownDependency {
+                        task: task.id.clone(),
+                        dependency: dep.clone(),
+                    });
+                };
+                waiting_on[index] += 1;
+                dependents[dep_index].push(index);
+            }
+        }
+
+        //Kahn's algorithm without running anything. Whatever never gets down to zero dependencies
+        // is stuck behind a cycle.
+        let mut remaining = waiting_on.clone();
+        let mut ready: Vec<usize> = (0..remaining.len()).filter(|&i| remaining[i] == 0).collect();
+        let mut reachable = 0;
+        while let Some(index) = ready.pop() {
+            reachable += 1;
+            for &dependent in &dependents[index] {
+                remaining[dependent] -= 1;
+                if remaining[dependent] == 0 {
+                    ready.push(dependent);
+                }
+            }
+        }
+        if reachable < self.tasks.len() {
+            let stuck = (0..remaining.len())
+                .filter(|&i| remaining[i] > 0)
+                .map(|i| self.tasks[i].id.clone())
+                .collect();
+            return Err(DagError::Cycle(stuck));
+        }
+
+        //Now the real run. The main thread hands ready tasks to the pool and each one reports back
+        // on `done_tx` when it finishes, which is when its dependents get their count lowered.
+        let pool = ThreadPool::new(workers);
+        let (done_tx, done_rx) = mpsc::channel();
+        let mut jobs: Vec<Option<Job>> = Vec::with_capacity(self.tasks.len());
+        let mut ids = Vec::with_capacity(self.tasks.len());
+        for task in self.tasks {
+            jobs.push(Some(task.job));
+            ids.push(task.id);
+        }
+
+        let mut start = |index: usize| {
+            let job = jobs[index].take().expect("A task only becomes ready once");
+            let done_tx = done_tx.clone();
+            pool.execute(move || {
+                job();
+                done_tx.send(index).expect("Runner hung up");
+            });
+        };
+
+        for index in (0..waiting_on.len()).filter(|&i| waiting_on[i] == 0) {
+            start(index);
+        }
+
+        let mut finished = Vec::with_capacity(ids.len());
+        while finished.len() < ids.len() {
+            let index = done_rx.recv().expect("A task panicked");
+            finished.push(ids[index].clone());
+            for &dependent in &dependents[index] {
+                waiting_on[dependent] -= 1;
+                if waiting_on[dependent] == 0 {
+                    start(dependent);
+                }
+            }
+        }
+
+        Ok(finished)
+    }
+}
+
+pub fn running_tasks_with_dependencies() {
+    //The diamond: b and c both need a, d needs both b and c.
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut runner = DagRunner::new();
+    for (id, deps) in [("d", vec!["b", "c"]), ("b", vec!["a"]), ("c", vec!["a"]), ("a", vec![])] {
+        let events = Arc::clone(&events);
+        runner.add_task(id, &deps, move || {
+            events.lock().unwrap().push(format!("start {id}"));
+            thread::sleep(Duration::from_millis(10));
+            events.lock().unwrap().push(format!("end {id}"));
+        });
+    }
+
+    let finished = runner.run(3).expect("The diamond has no cycles");
+    let events = events.lock().unwrap();
+    let position = |event: &str| events.iter().position(|e| e == event).unwrap();
+    let constraints_hold = [("a", "b"), ("a", "c"), ("b", "d"), ("c", "d")]
+        .iter()
+        .all(|(before, after)| {
+            position(&format!("end {before}")) < position(&format!("start {after}"))
+        });
+
+    println!("diamond events: {:?}", *events);
+    println!("diamond finished: {:?}, dependencies respected: {constraints_hold}", finished);
+    println!("every task ran once: {}", events.len() == 8);
+
+    let mut cyclic = DagRunner::new();
+    cyclic.add_task("x", &["z"], || {});
+    cyclic.add_task("y", &["x"], || {});
+    cyclic.add_task("z", &["y"], || {});
+    cyclic.add_task("w", &[], || {});
+    match cyclic.run(2) {
+        Ok(finished) => println!("cyclic graph somehow finished: {:?}", finished),
+        Err(error) => println!("running a cyclic graph: {error}"),
+    }
+
+    let mut missing = DagRunner::new();
+    missing.add_task("a", &["nope"], || {});
+    if let Err(error) = missing.run(1) {
+        println!("running a graph with a missing task: {error}");
+    }
+
+    let mut duplicated = DagRunner::new();
+    duplicated.add_task("a", &[], || {});
+    duplicated.add_task("a", &[], || {});
+    if let Err(error) = duplicated.run(1) {
+        println!("running a graph with a duplicate task: {error}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    //Records (task, poll number) for every poll so the test can check the interleaving.
+    fn logged_task(id: usize, yields: usize, log: Arc<Mutex<Vec<(usize, usize)>>>) -> Task<usize> {
+        let mut polls = 0;
+        Box::new(move || {
+            polls += 1;
+            log.lock().unwrap().push((id, polls));
+            if polls <= yields {
+                Poll::Pending
+            } else {
+                Poll::Ready(polls)
+            }
+        })
+    }
+
+    #[test]
+    fn pending_tasks_take_turns_until_ready() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let scheduler = CooperativeScheduler::new(2);
+
+        let results = scheduler.run(vec![
+            logged_task(0, 2, Arc::clone(&log)),
+            logged_task(1, 4, Arc::clone(&log)),
+        ]);
+        assert_eq!(results, vec![3, 5]);
+
+        //Every pending task is polled once per round, so no task gets its next poll before the
+        // other one has had its turn in the current round.
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 3 + 5);
+        assert!(log.windows(2).all(|pair| pair[0].1 <= pair[1].1), "{log:?}");
+        for round in 1..=3 {
+            assert_eq!(log.iter().filter(|(_, poll)| *poll == round).count(), 2);
+        }
+    }
+
+    #[test]
+    fn dag_tasks_start_only_after_their_dependencies() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = DagRunner::new();
+        let graph = [("d", vec!["b", "c"]), ("b", vec!["a"]), ("c", vec!["a"]), ("a", vec![])];
+        for (id, deps) in graph {
+            let events = Arc::clone(&events);
+            runner.add_task(id, &deps, move || {
+                events.lock().unwrap().push(format!("start {id}"));
+                thread::sleep(Duration::from_millis(5));
+                events.lock().unwrap().push(format!("end {id}"));
+            });
+        }
+
+        let finished = runner.run(3).unwrap();
+        assert_eq!(finished.len(), 4);
+        assert_eq!(finished[0], "a");
+        assert_eq!(finished[3], "d");
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 8);
+        let position = |event: String| events.iter().position(|e| *e == event).unwrap();
+        for (before, after) in [("a", "b"), ("a", "c"), ("b", "d"), ("c", "d")] {
+            assert!(position(format!("end {before}")) < position(format!("start {after}")));
+        }
+    }
+
+    #[test]
+    fn cycles_are_reported_with_everything_stuck_behind_them() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        let mut runner = DagRunner::new();
+        for (id, deps) in [("x", vec!["z"]), ("y", vec!["x"]), ("z", vec!["y"]), ("w", vec![])] {
+            let ran = Arc::clone(&ran);
+            runner.add_task(id, &deps, move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        runner.add_task("after", &["x", "w"], || {});
+
+        match runner.run(2) {
+            Err(DagError::Cycle(stuck)) => assert_eq!(stuck, ["x", "y", "z", "after"]),
+            other => panic!("expected a cycle, got {other:?}"),
+        }
+        //The graph is checked before anything runs, so not even w got to run.
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn unknown_and_duplicate_tasks_are_rejected() {
+        let mut missing = DagRunner::new();
+        missing.add_task("a", &["nope"], || {});
+        match missing.run(1) {
+            Err(DagError::UnknownDependency { task, dependency }) => {
+                assert_eq!((task.as_str(), dependency.as_str()), ("a", "nope"));
+            }
+            other => panic!("expected an unknown dependency, got {other:?}"),
+        }
+
+        let mut duplicated = DagRunner::new();
+        duplicated.add_task("a", &[], || {});
+        duplicated.add_task("a", &[], || {});
+        assert!(matches!(duplicated.run(1), Err(DagError::DuplicateTask(id)) if id == "a"));
+    }
+}
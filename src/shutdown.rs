@@ -0,0 +1,320 @@
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::Duration;
+
+//Asks workers to stop. Firing the trigger flips a shared flag that every token made from it can
+// see. Workers are expected to check their token every so often and wind down on their own,
+// nothing is forcibly stopped.
+#[derive(Clone, Default)]
+pub struct ShutdownTrigger {
+    fired: Arc<AtomicBool>,
+}
+
+//The read only half that gets handed to workers. It can see the shutdown but can't cause one.
+#[derive(Clone)]
+pub struct ShutdownToken {
+    fired: Arc<AtomicBool>,
+}
+
+//Stopping work early for any reason is the same thing as shutting down, just on a smaller scale.
+pub type CancellationToken = ShutdownToken;
+
+impl ShutdownTrigger {
+    pub fn new() -> ShutdownTrigger {
+        ShutdownTrigger::default()
+    }
+
+    pub fn fire(&self) {
+        self.fired.store(true, Ordering::SeqCst);
+    }
+
+    pub fn token(&self) -> ShutdownToken {
+        ShutdownToken {
+            fired: Arc::clone(&self.fired),
+        }
+    }
+}
+
+impl ShutdownToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.fired.load(Ordering::SeqCst)
+    }
+}
+
+pub const SIGINT: c_int = 2;
+
+//Set by the signal handler and picked up by the watcher thread.
+static CTRL_C_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+//A signal handler can interrupt the program anywhere, even in the middle of a lock() or an
+// allocation, so it must not do either of those. Storing to an atomic is about the only thing
+// that is safe to do, the watcher thread takes care of the rest.
+pub extern "C" fn handle_sigint(_signal: c_int) {
+    CTRL_C_RECEIVED.store(true, Ordering::SeqCst);
+}
+
+//Without any dependencies the portable way to hook up Ctrl-C is the C standard library's
+// signal(), which std already links against. The ctrlc crate does the same thing more carefully
+// (sigaction() on unix, SetConsoleCtrlHandler() on Windows). On other platforms only the
+// simulated path works, by calling handle_sigint() directly.
+#[cfg(unix)]
+mod ffi {
+    use std::os::raw::c_int;
+
+    pub const SIG_DFL: usize = 0;
+
+    extern "C" {
+        pub fn signal(signum: c_int, handler: usize) -> usize;
+    }
+}
+
+#[cfg(unix)]
+fn set_sigint_handler(handler: usize) {
+    //Safe because the handler is either SIG_DFL or handle_sigint(), which only touches an atomic.
+    unsafe {
+        ffi::signal(SIGINT, handler);
+    }
+}
+
+#[cfg(not(unix))]
+fn set_sigint_handler(_handler: usize) {}
+
+//Keeps Ctrl-C wired to the trigger for as long as it is alive. Dropping it puts back the default
+// behavior of Ctrl-C killing the program and stops the watcher thread.
+#[must_use = "Ctrl-C goes back to the default behavior once this is dropped"]
+pub struct CtrlCShutdown {
+    stop: Option<mpsc::Sender<()>>,
+    watcher: Option<thread::JoinHandle<()>>,
+}
+
+pub fn install_ctrlc_shutdown(trigger: ShutdownTrigger) -> CtrlCShutdown {
+    CTRL_C_RECEIVED.store(false, Ordering::SeqCst);
+    set_sigint_handler(handle_sigint as extern "C" fn(c_int) as usize);
+
+    let (stop, stop_rx) = mpsc::channel::<()>();
+    let watcher = thread::spawn(move || {
+        while let Err(mpsc::RecvTimeoutError::Timeout) =
+            stop_rx.recv_timeout(Duration::from_millis(10))
+        {
+            if CTRL_C_RECEIVED.swap(false, Ordering::SeqCst) {
+                trigger.fire();
+            }
+        }
+    });
+
+    CtrlCShutdown {
+        stop: Some(stop),
+        watcher: Some(watcher),
+    }
+}
+
+impl Drop for CtrlCShutdown {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        set_sigint_handler(ffi::SIG_DFL);
+
+        drop(self.stop.take());
+        if let Some(watcher) = self.watcher.take() {
+            watcher.join().expect("Ctrl-C watcher crashed");
+        }
+    }
+}
+
+//Every job gets its own thread and sends its result back when it's done. The collector waits on
+// the results with a short timeout so it can keep checking the token. Once the token is
+// cancelled it stops collecting and returns what has arrived, in the order it arrived. Jobs that
+// are still running can't be stopped from the outside, they run to the end and their send()
+// fails quietly because nobody is receiving any more.
+pub fn gather_until_cancelled<T, F>(jobs: Vec<F>, token: ShutdownToken) -> Vec<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    let total = jobs.len();
+
+    for job in jobs {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let _ = tx.send(job());
+        });
+    }
+    drop(tx);
+
+    let mut results = Vec::with_capacity(total);
+    while results.len() < total && !token.is_cancelled() {
+        match rx.recv_timeout(Duration::from_millis(5)) {
+            Ok(result) => results.push(result),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            //Only happens if a job panicked, everything else has been received already.
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    results
+}
+
+pub fn keeping_partial_results_after_cancel() {
+    let trigger = ShutdownTrigger::new();
+
+    //Pretend downloads, the nth one takes n * 20ms.
+    let downloads: Vec<_> = (1..=10u64)
+        .map(|n| {
+            move || {
+                thread::sleep(Duration::from_millis(n * 20));
+                format!("file {n}")
+            }
+        })
+        .collect();
+
+    let canceller = {
+        let trigger = trigger.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(90));
+            trigger.fire();
+        })
+    };
+
+    let finished = gather_until_cancelled(downloads, trigger.token());
+    println!("downloads finished before the cancel: {:?} of 10", finished);
+    canceller.join().expect("Canceller crashed");
+}
+
+pub fn shutting_down_on_ctrl_c() {
+    let trigger = ShutdownTrigger::new();
+    let ctrlc = install_ctrlc_shutdown(trigger.clone());
+
+    let workers: Vec<_> = (0..3)
+        .map(|id| {
+            let token = trigger.token();
+            thread::spawn(move || {
+                let mut rounds = 0;
+                while !token.is_cancelled() {
+                    rounds += 1;
+                    thread::sleep(Duration::from_millis(5));
+                }
+                println!("worker {id} stopped after {rounds} rounds");
+            })
+        })
+        .collect();
+
+    //Pretend the user pressed Ctrl-C by calling the handler the same way the OS would.
+    thread::sleep(Duration::from_millis(30));
+    handle_sigint(SIGINT);
+
+    for worker in workers {
+        worker.join().expect("Worker crashed");
+    }
+    println!("shutdown fired by the Ctrl-C handler: {}", trigger.token().is_cancelled());
+
+    drop(ctrlc);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    use super::*;
+
+    fn spawn_workers(token: &ShutdownToken, count: usize) -> Vec<thread::JoinHandle<u32>> {
+        (0..count)
+            .map(|_| {
+                let token = token.clone();
+                thread::spawn(move || {
+                    let mut rounds = 0;
+                    while !token.is_cancelled() {
+                        rounds += 1;
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    rounds
+                })
+            })
+            .collect()
+    }
+
+    fn join_within(workers: Vec<thread::JoinHandle<u32>>, limit: Duration) {
+        let deadline = Instant::now() + limit;
+        for worker in workers {
+            while !worker.is_finished() {
+                assert!(Instant::now() < deadline, "A worker kept running after the shutdown");
+                thread::sleep(Duration::from_millis(5));
+            }
+            worker.join().expect("Worker crashed");
+        }
+    }
+
+    #[test]
+    fn workers_stop_once_the_trigger_fires() {
+        let trigger = ShutdownTrigger::new();
+        let token = trigger.token();
+        let workers = spawn_workers(&token, 3);
+
+        thread::sleep(Duration::from_millis(20));
+        assert!(!token.is_cancelled());
+        assert!(workers.iter().all(|worker| !worker.is_finished()));
+
+        trigger.fire();
+        assert!(token.is_cancelled());
+        join_within(workers, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn ctrl_c_handler_fires_the_trigger() {
+        let trigger = ShutdownTrigger::new();
+        let ctrlc = install_ctrlc_shutdown(trigger.clone());
+        let workers = spawn_workers(&trigger.token(), 2);
+
+        handle_sigint(SIGINT);
+        join_within(workers, Duration::from_secs(5));
+        assert!(trigger.token().is_cancelled());
+
+        drop(ctrlc);
+    }
+
+    #[test]
+    fn cancelling_keeps_the_results_that_already_arrived() {
+        let trigger = ShutdownTrigger::new();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = Arc::new(Mutex::new(release_rx));
+
+        //Jobs 0 to 2 finish straight away, jobs 3 to 5 are stuck until after the cancel.
+        let jobs: Vec<Box<dyn FnOnce() -> usize + Send>> = (0..6)
+            .map(|n| -> Box<dyn FnOnce() -> usize + Send> {
+                let release_rx = Arc::clone(&release_rx);
+                Box::new(move || {
+                    if n >= 3 {
+                        let _ = release_rx.lock().unwrap().recv();
+                    }
+                    n
+                })
+            })
+            .collect();
+
+        let canceller = {
+            let trigger = trigger.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(300));
+                trigger.fire();
+            })
+        };
+
+        let mut finished = gather_until_cancelled(jobs, trigger.token());
+        finished.sort();
+        assert_eq!(finished, vec![0, 1, 2]);
+
+        canceller.join().unwrap();
+        drop(release_tx);
+    }
+
+    #[test]
+    fn without_a_cancel_every_result_is_gathered() {
+        let trigger = ShutdownTrigger::new();
+        let jobs: Vec<_> = (0..8).map(|n| move || n * n).collect();
+        let mut finished = gather_until_cancelled(jobs, trigger.token());
+        finished.sort();
+        assert_eq!(finished, vec![0, 1, 4, 9, 16, 25, 36, 49]);
+    }
+}
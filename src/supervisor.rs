@@ -0,0 +1,470 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::Duration;
+
+type WorkerTask = Arc<dyn Fn() + Send + Sync + 'static>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WorkerOutcome {
+    //The worker returned normally.
+    Completed,
+    //The worker kept panicking and ran out of restarts.
+    GaveUp,
+}
+
+//Keeps a set of worker threads running. A panic in a spawned thread doesn't take down the rest of
+// the program, it just shows up as an Err when the thread is joined, so the supervisor joins each
+// worker as soon as it exits and spawns it again if it panicked. The tasks are Fn instead of
+// FnOnce so the same task can be started as many times as needed.
+pub struct Supervisor {
+    max_restarts: usize,
+    workers: Vec<SupervisedWorker>,
+    exited_tx: mpsc::Sender<usize>,
+    exited_rx: mpsc::Receiver<usize>,
+}
+
+struct SupervisedWorker {
+    task: WorkerTask,
+    restarts: usize,
+    handle: Option<thread::JoinHandle<()>>,
+    outcome: Option<WorkerOutcome>,
+}
+
+//Sends the worker id when it gets dropped. Drop still runs while a panic is unwinding the stack,
+// so the supervisor hears about a worker exiting whether it returned or panicked.
+struct ExitNotifier {
+    id: usize,
+    exited_tx: mpsc::Sender<usize>,
+}
+
+impl Drop for ExitNotifier {
+    fn drop(&mut self) {
+        let _ = self.exited_tx.send(self.id);
+    }
+}
+
+impl Supervisor {
+    pub fn new(max_restarts: usize) -> Supervisor {
+        let (exited_tx, exited_rx) = mpsc::channel();
+
+        Supervisor {
+            max_restarts,
+            workers: Vec::new(),
+            exited_tx,
+            exited_rx,
+        }
+    }
+
+    //Starts the worker right away and returns its id.
+    pub fn spawn<F>(&mut self, task: F) -> usize
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let id = self.workers.len();
+        let task: WorkerTask = Arc::new(task);
+
+        self.workers.push(SupervisedWorker {
+            handle: Some(self.start(id, Arc::clone(&task))),
+            task,
+            restarts: 0,
+            outcome: None,
+        });
+
+        id
+    }
+
+    fn start(&self, id: usize, task: WorkerTask) -> thread::JoinHandle<()> {
+        let exited_tx = self.exited_tx.clone();
+
+        thread::spawn(move || {
+            let _notifier = ExitNotifier { id, exited_tx };
+            task();
+        })
+    }
+
+    pub fn restart_count(&self, worker_id: usize) -> usize {
+        self.workers[worker_id].restarts
+    }
+
+    //Blocks until every worker has either completed or given up, restarting panicked workers
+    // along the way. Returns the outcome of each worker in id order.
+    pub fn supervise(&mut self) -> Vec<WorkerOutcome> {
+        while self.workers.iter().any(|worker| worker.outcome.is_none()) {
+            let id = self.exited_rx.recv().expect("Supervisor holds a sender");
+
+            let handle = self.workers[id].handle.take().expect("Exited worker was running");
+            let panicked = handle.join().is_err();
+
+            let worker = &mut self.workers[id];
+            if !panicked {
+                worker.outcome = Some(WorkerOutcome::Completed);
+            } else if worker.restarts < self.max_restarts {
+                worker.restarts += 1;
+                let task = Arc::clone(&worker.task);
+                self.workers[id].handle = Some(self.start(id, task));
+            } else {
+                worker.outcome = Some(WorkerOutcome::GaveUp);
+            }
+        }
+
+        self.workers
+            .iter()
+            .map(|worker| worker.outcome.expect("Loop only ends once every worker is done"))
+            .collect()
+    }
+}
+
+pub fn restarting_panicked_workers() {
+    let mut supervisor = Supervisor::new(3);
+
+    //Fails on its first two attempts and then runs to completion.
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let flaky = {
+        let attempts = Arc::clone(&attempts);
+        supervisor.spawn(move || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            if attempt <= 2 {
+                panic!("flaky worker failed on attempt {attempt}");
+            }
+            println!("flaky worker succeeded on attempt {attempt}");
+        })
+    };
+
+    let hopeless = supervisor.spawn(|| panic!("hopeless worker always fails"));
+
+    let outcomes = supervisor.supervise();
+    println!(
+        "flaky worker: {:?} after {} restarts",
+        outcomes[flaky],
+        supervisor.restart_count(flaky)
+    );
+    println!(
+        "hopeless worker: {:?} after {} restarts",
+        outcomes[hopeless],
+        supervisor.restart_count(hopeless)
+    );
+}
+
+//Keeps count of how many of the threads it spawned have finished. Each thread holds a
+// FinishedGuard that bumps the shared counter when it's dropped, which happens on a normal return
+// and while a panic unwinds, so a crashed thread still counts as finished.
+#[derive(Default)]
+pub struct CompletionCounter {
+    spawned: AtomicUsize,
+    finished: Arc<AtomicUsize>,
+}
+
+struct FinishedGuard(Arc<AtomicUsize>);
+
+impl Drop for FinishedGuard {
+    fn drop(&mut self) {
+        //Release pairs with the Acquire load in try_join_all(), once the count includes this
+        // thread everything it did before finishing is visible too.
+        self.0.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl CompletionCounter {
+    pub fn new() -> CompletionCounter {
+        CompletionCounter::default()
+    }
+
+    pub fn spawn<F, T>(&self, f: F) -> thread::JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawned.fetch_add(1, Ordering::SeqCst);
+        let finished = Arc::clone(&self.finished);
+
+        thread::spawn(move || {
+            let _finished = FinishedGuard(finished);
+            f()
+        })
+    }
+
+    //Joins every handle, but only once the counter says every thread spawned through it has
+    // finished, so none of the joins can block for more than the moment it takes a thread to exit
+    // after dropping its guard. If any thread is still running the handles are left alone and None
+    // is returned so the caller can try again later. Otherwise the handles are drained out of the
+    // Vec, and a thread that panicked shows up as an Err in the results instead of a panic here.
+    pub fn try_join_all<T>(
+        &self,
+        handles: &mut Vec<Option<thread::JoinHandle<T>>>,
+    ) -> Option<Vec<thread::Result<T>>> {
+        if self.finished.load(Ordering::Acquire) < self.spawned.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        Some(handles.drain(..).flatten().map(thread::JoinHandle::join).collect())
+    }
+}
+
+pub fn polling_for_completion() {
+    let completion = CompletionCounter::new();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+
+    let mut handles = vec![
+        Some(completion.spawn(|| 1)),
+        Some(completion.spawn(move || {
+            //Stays running until the main thread lets it go.
+            let _ = release_rx.recv();
+            2
+        })),
+    ];
+
+    println!(
+        "try_join_all while a thread is running: {:?}",
+        completion.try_join_all(&mut handles)
+    );
+
+    drop(release_tx);
+    let results = loop {
+        if let Some(results) = completion.try_join_all(&mut handles) {
+            break results;
+        }
+        thread::yield_now();
+    };
+
+    let results: Vec<i32> = results
+        .into_iter()
+        .map(|result| result.expect("Polled thread crashed"))
+        .collect();
+    println!("try_join_all after every thread finished: {:?}", results);
+}
+
+//Owns a group of threads and joins all of them when it goes out of scope, so there's no way to
+// forget the `for thread in threads { thread.join() }` loop. A panic in one of the threads gets
+// passed on when it's joined, unless this thread is already panicking, since panicking during
+// an unwind would abort the whole program.
+#[derive(Default)]
+pub struct JoinOnDrop {
+    handles: Vec<thread::JoinHandle<()>>,
+}
+
+impl JoinOnDrop {
+    pub fn new() -> JoinOnDrop {
+        JoinOnDrop::default()
+    }
+
+    pub fn push(&mut self, handle: thread::JoinHandle<()>) {
+        self.handles.push(handle);
+    }
+}
+
+impl Drop for JoinOnDrop {
+    fn drop(&mut self) {
+        for handle in self.handles.drain(..) {
+            if handle.join().is_err() && !thread::panicking() {
+                panic!("A thread owned by JoinOnDrop panicked");
+            }
+        }
+    }
+}
+
+pub fn joining_threads_on_drop() {
+    let finished = Arc::new(AtomicUsize::new(0));
+
+    {
+        let mut threads = JoinOnDrop::new();
+        for i in 0..5u64 {
+            let finished = Arc::clone(&finished);
+            threads.push(thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10 * i));
+                finished.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+        //`threads` goes out of scope here and every thread is joined.
+    }
+
+    println!("threads finished once JoinOnDrop was dropped: {}", finished.load(Ordering::SeqCst));
+}
+
+//The opposite of JoinOnDrop. Instead of quietly joining, this complains when a handle is dropped
+// without join() having been called, since dropping a JoinHandle detaches the thread and nothing
+// will ever see its result or its panic. Debug builds panic so the leak gets noticed straight
+// away, release builds only print a warning.
+pub struct MustJoin<T> {
+    handle: Option<thread::JoinHandle<T>>,
+}
+
+impl<T> MustJoin<T> {
+    pub fn new(handle: thread::JoinHandle<T>) -> MustJoin<T> {
+        MustJoin {
+            handle: Some(handle),
+        }
+    }
+
+    pub fn join(mut self) -> thread::Result<T> {
+        self.handle
+            .take()
+            .expect("The handle is only taken by join()")
+            .join()
+    }
+}
+
+impl<T> Drop for MustJoin<T> {
+    fn drop(&mut self) {
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+
+        let name = handle.thread().name().unwrap_or("unnamed").to_string();
+        if cfg!(debug_assertions) && !thread::panicking() {
+            panic!("MustJoin for thread {name} was dropped without being joined");
+        }
+        eprintln!("warning: MustJoin for thread {name} was dropped without being joined");
+    }
+}
+
+pub fn catching_unjoined_threads() {
+    let joined = MustJoin::new(thread::spawn(|| 7));
+    println!("joined MustJoin returned: {:?}", joined.join());
+
+    //The panic is expected here, so the default hook that prints it and the backtrace note is
+    // swapped out for a silent one just while it happens.
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let leaked = std::panic::catch_unwind(|| {
+        let handle = thread::Builder::new()
+            .name(String::from("leaky"))
+            .spawn(|| {})
+            .expect("Failed to spawn thread");
+        drop(MustJoin::new(handle));
+    });
+    std::panic::set_hook(default_hook);
+    println!(
+        "dropping a MustJoin without joining panicked: {} (debug build: {})",
+        leaked.is_err(),
+        cfg!(debug_assertions)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn supervisor_restarts_until_success_or_the_limit() {
+        let mut supervisor = Supervisor::new(3);
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let flaky = {
+            let attempts = Arc::clone(&attempts);
+            supervisor.spawn(move || {
+                if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                    panic!("flaky worker failed");
+                }
+            })
+        };
+        let steady = supervisor.spawn(|| {});
+        let hopeless = supervisor.spawn(|| panic!("hopeless worker always fails"));
+
+        let outcomes = supervisor.supervise();
+
+        assert_eq!(outcomes[flaky], WorkerOutcome::Completed);
+        assert_eq!(supervisor.restart_count(flaky), 2);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        assert_eq!(outcomes[steady], WorkerOutcome::Completed);
+        assert_eq!(supervisor.restart_count(steady), 0);
+
+        assert_eq!(outcomes[hopeless], WorkerOutcome::GaveUp);
+        assert_eq!(supervisor.restart_count(hopeless), 3);
+    }
+
+    #[test]
+    fn try_join_all_waits_for_every_thread() {
+        let completion = CompletionCounter::new();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        let mut handles = vec![
+            Some(completion.spawn(|| 1)),
+            Some(completion.spawn(move || {
+                let _ = release_rx.recv();
+                2
+            })),
+        ];
+
+        assert!(completion.try_join_all(&mut handles).is_none());
+        assert_eq!(handles.len(), 2);
+
+        drop(release_tx);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let results = loop {
+            if let Some(results) = completion.try_join_all(&mut handles) {
+                break results;
+            }
+            assert!(Instant::now() < deadline, "Threads never finished");
+            thread::yield_now();
+        };
+
+        let results: Vec<i32> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(results, vec![1, 2]);
+        assert!(handles.is_empty());
+    }
+
+    #[test]
+    fn try_join_all_returns_panics_as_errors() {
+        let completion = CompletionCounter::new();
+        let mut handles = vec![
+            Some(completion.spawn(|| 1)),
+            Some(completion.spawn(|| panic!("polled thread failed"))),
+        ];
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let results = loop {
+            if let Some(results) = completion.try_join_all(&mut handles) {
+                break results;
+            }
+            assert!(Instant::now() < deadline, "Threads never finished");
+            thread::yield_now();
+        };
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(*results[0].as_ref().unwrap(), 1);
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn join_on_drop_waits_for_every_thread() {
+        let finished = Arc::new(AtomicUsize::new(0));
+
+        {
+            let mut threads = JoinOnDrop::new();
+            for i in 0..5u64 {
+                let finished = Arc::clone(&finished);
+                threads.push(thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(10 * i));
+                    finished.fetch_add(1, Ordering::SeqCst);
+                }));
+            }
+        }
+
+        assert_eq!(finished.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "A thread owned by JoinOnDrop panicked")]
+    fn join_on_drop_passes_on_a_panic() {
+        let mut threads = JoinOnDrop::new();
+        threads.push(thread::spawn(|| panic!("owned thread failed")));
+    }
+
+    #[test]
+    fn joined_must_join_hands_back_the_result() {
+        let joined = MustJoin::new(thread::spawn(|| 7));
+        assert_eq!(joined.join().unwrap(), 7);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "MustJoin for thread leaky was dropped without being joined")]
+    fn dropping_an_unjoined_must_join_panics_in_debug_builds() {
+        let handle = thread::Builder::new().name(String::from("leaky")).spawn(|| {}).unwrap();
+        drop(MustJoin::new(handle));
+    }
+}
@@ -0,0 +1,729 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//std doesn't come with a semaphore, but one is easy to build out of a Mutex<T> and a Condvar. The
+// mutex protects the number of permits that are left and the condvar lets threads sleep until a
+// permit is given back instead of spinning on the lock.
+pub struct Semaphore {
+    permits: Mutex<usize>,
+    available: Condvar,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            permits: Mutex::new(permits),
+            available: Condvar::new(),
+        }
+    }
+
+    //Blocks until a permit is free. The permit is given back when the returned guard is dropped.
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        let permits = self.permits.lock().unwrap();
+        let mut permits = self
+            .available
+            .wait_while(permits, |permits| *permits == 0)
+            .unwrap();
+        *permits -= 1;
+
+        SemaphorePermit { semaphore: self }
+    }
+
+    fn release(&self) {
+        *self.permits.lock().unwrap() += 1;
+        self.available.notify_one();
+    }
+}
+
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
+
+//Runs closures on whatever thread calls run(), but never lets more than `limit` of them run at
+// the same time. Callers past the limit just block until one of the running closures finishes.
+pub struct ConcurrencyGate {
+    semaphore: Semaphore,
+}
+
+impl ConcurrencyGate {
+    pub fn new(limit: usize) -> ConcurrencyGate {
+        assert!(limit > 0, "A gate with no permits would block forever");
+
+        ConcurrencyGate {
+            semaphore: Semaphore::new(limit),
+        }
+    }
+
+    pub fn run<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        let _permit = self.semaphore.acquire();
+        f()
+    }
+}
+
+pub fn limiting_concurrency_with_a_gate() {
+    let gate = Arc::new(ConcurrencyGate::new(2));
+    let running = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+
+    let handles: Vec<_> = (0..6)
+        .map(|i| {
+            let gate = Arc::clone(&gate);
+            let running = Arc::clone(&running);
+            let peak = Arc::clone(&peak);
+            thread::spawn(move || {
+                gate.run(|| {
+                    let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now_running, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    i
+                })
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().expect("Gated thread crashed");
+    }
+
+    println!("peak concurrent runs through the gate: {}", peak.load(Ordering::SeqCst));
+}
+
+//Semaphore hands a released permit to whichever waiting thread the OS happens to wake up first,
+// so an unlucky thread can keep losing forever. FairSemaphore works like the ticket machine at a
+// deli counter. Every caller of acquire() takes the next ticket and only the ticket that is being
+// served is allowed to take a permit, so permits go out in the order acquire() was called.
+pub struct FairSemaphore {
+    state: Mutex<FairState>,
+    changed: Condvar,
+}
+
+struct FairState {
+    permits: usize,
+    next_ticket: u64,
+    now_serving: u64,
+}
+
+impl FairSemaphore {
+    pub fn new(permits: usize) -> FairSemaphore {
+        FairSemaphore {
+            state: Mutex::new(FairState {
+                permits,
+                next_ticket: 0,
+                now_serving: 0,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self) -> FairSemaphorePermit<'_> {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+
+        let mut state = self
+            .changed
+            .wait_while(state, |state| state.now_serving != ticket || state.permits == 0)
+            .unwrap();
+        state.permits -= 1;
+        state.now_serving += 1;
+
+        //notify_all() instead of notify_one() because only the thread holding the next ticket can
+        // go, and there's no way to wake up one specific thread.
+        self.changed.notify_all();
+
+        FairSemaphorePermit { semaphore: self }
+    }
+
+    //How many threads are currently waiting in acquire().
+    pub fn queued(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        (state.next_ticket - state.now_serving) as usize
+    }
+
+    fn release(&self) {
+        self.state.lock().unwrap().permits += 1;
+        self.changed.notify_all();
+    }
+}
+
+pub struct FairSemaphorePermit<'a> {
+    semaphore: &'a FairSemaphore,
+}
+
+impl Drop for FairSemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
+
+pub fn granting_permits_in_arrival_order() {
+    let semaphore = Arc::new(FairSemaphore::new(1));
+    let acquired_order = Arc::new(Mutex::new(Vec::new()));
+
+    //Holding the only permit makes every thread below queue up.
+    let held = semaphore.acquire();
+
+    let mut handles = Vec::new();
+    for id in 0..5 {
+        let semaphore_clone = Arc::clone(&semaphore);
+        let acquired_order = Arc::clone(&acquired_order);
+        handles.push(thread::spawn(move || {
+            let _permit = semaphore_clone.acquire();
+            acquired_order.lock().unwrap().push(id);
+        }));
+
+        //The next thread isn't started until this one has its ticket, so the arrival order is
+        // exactly 0, 1, 2, 3, 4.
+        while semaphore.queued() < id + 1 {
+            thread::yield_now();
+        }
+    }
+
+    drop(held);
+    for handle in handles {
+        handle.join().expect("Fair semaphore thread crashed");
+    }
+
+    println!("fair semaphore acquisition order: {:?}", acquired_order.lock().unwrap());
+}
+
+//std's Barrier waits forever, so if one of the threads dies before it gets there everybody else
+// hangs. Here every wait() has a timeout. The first thread to time out breaks the barrier, and a
+// broken barrier fails every current and future wait() right away, so the remaining threads find
+// out quickly instead of each waiting out their own timeout. Once broken it stays broken.
+pub struct TimeoutBarrier {
+    parties: usize,
+    state: Mutex<TimeoutBarrierState>,
+    changed: Condvar,
+}
+
+struct TimeoutBarrierState {
+    arrived: usize,
+    generation: u64,
+    broken: bool,
+}
+
+//Same idea as std::sync::BarrierWaitResult, exactly one thread per generation is the leader.
+#[derive(Debug)]
+pub struct BarrierWaitResult {
+    is_leader: bool,
+}
+
+impl BarrierWaitResult {
+    pub fn is_leader(&self) -> bool {
+        self.is_leader
+    }
+}
+
+#[derive(Debug)]
+pub struct BarrierTimeout;
+
+impl TimeoutBarrier {
+    pub fn new(parties: usize) -> TimeoutBarrier {
+        assert!(parties > 0, "A barrier needs at least one participant");
+
+        TimeoutBarrier {
+            parties,
+            state: Mutex::new(TimeoutBarrierState {
+                arrived: 0,
+                generation: 0,
+                broken: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn wait(&self, dur: Duration) -> Result<BarrierWaitResult, BarrierTimeout> {
+        let deadline = Instant::now() + dur;
+        let mut state = self.state.lock().unwrap();
+
+        if state.broken {
+            return Err(BarrierTimeout);
+        }
+
+        state.arrived += 1;
+        if state.arrived == self.parties {
+            state.arrived = 0;
+            state.generation += 1;
+            self.changed.notify_all();
+            return Ok(BarrierWaitResult { is_leader: true });
+        }
+
+        //A new generation number is how the waiters can tell that the last thread arrived, as
+        // opposed to a spurious wakeup.
+        let generation = state.generation;
+        while state.generation == generation && !state.broken {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                state.broken = true;
+                self.changed.notify_all();
+                break;
+            }
+            state = self.changed.wait_timeout(state, remaining).unwrap().0;
+        }
+
+        if state.generation != generation {
+            Ok(BarrierWaitResult { is_leader: false })
+        } else {
+            Err(BarrierTimeout)
+        }
+    }
+}
+
+pub fn barrier_with_a_timeout() {
+    let barrier = TimeoutBarrier::new(3);
+
+    let results: Vec<Result<bool, BarrierTimeout>> = thread::scope(|s| {
+        let handles: Vec<_> = (0..3)
+            .map(|_| s.spawn(|| barrier.wait(Duration::from_millis(100)).map(|r| r.is_leader())))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Barrier thread crashed"))
+            .collect()
+    });
+    println!("everybody arrived (leader flags): {:?}", results);
+
+    //Three parties are expected but only two ever show up. The second thread would wait a lot
+    // longer, but it fails as soon as the first one times out and breaks the barrier.
+    let barrier = TimeoutBarrier::new(3);
+    let results: Vec<Result<bool, BarrierTimeout>> = thread::scope(|s| {
+        let handles: Vec<_> = [50, 5000]
+            .into_iter()
+            .map(|millis| {
+                let barrier = &barrier;
+                s.spawn(move || {
+                    barrier
+                        .wait(Duration::from_millis(millis))
+                        .map(|result| result.is_leader())
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Barrier thread crashed"))
+            .collect()
+    });
+    println!("one participant missing: {:?}", results);
+}
+
+//Go's sync.WaitGroup. add() says how many things are outstanding, each of them calls done() when
+// it finishes, and wait() blocks until the count gets back to zero.
+pub struct WaitGroup {
+    count: Mutex<usize>,
+    zero: Condvar,
+}
+
+impl WaitGroup {
+    pub fn new() -> WaitGroup {
+        WaitGroup {
+            count: Mutex::new(0),
+            zero: Condvar::new(),
+        }
+    }
+
+    pub fn add(&self, n: usize) {
+        *self.count.lock().unwrap() += n;
+    }
+
+    pub fn done(&self) {
+        let mut count = self.count.lock().unwrap();
+        *count = count.checked_sub(1).expect("done() called more times than add()");
+        if *count == 0 {
+            self.zero.notify_all();
+        }
+    }
+
+    pub fn wait(&self) {
+        let count = self.count.lock().unwrap();
+        let _count = self.zero.wait_while(count, |count| *count > 0).unwrap();
+    }
+
+    //Same as wait() but gives up after `timeout`. Returns whether the count got to zero in time.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let count = self.count.lock().unwrap();
+        let (_count, result) = self
+            .zero
+            .wait_timeout_while(count, timeout, |count| *count > 0)
+            .unwrap();
+        !result.timed_out()
+    }
+}
+
+//A one way switch that threads can wait on. Once set() it stays set, so a thread that only gets
+// to wait() afterwards goes straight through instead of missing the signal, which is the usual
+// trap with a bare Condvar.
+pub struct Event {
+    set: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl Event {
+    pub fn new() -> Event {
+        Event {
+            set: Mutex::new(false),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn set(&self) {
+        *self.set.lock().unwrap() = true;
+        self.changed.notify_all();
+    }
+
+    pub fn wait(&self) {
+        let set = self.set.lock().unwrap();
+        let _set = self.changed.wait_while(set, |set| !*set).unwrap();
+    }
+}
+
+//Two stage startup. Every worker checks in with ready() once it has finished setting itself up
+// and then waits there. The coordinator waits until all of them have checked in, does whatever it
+// needs to with everybody known to be ready, and then lets them all go at once.
+pub struct StartupCoordinator {
+    ready: WaitGroup,
+    go: Event,
+}
+
+impl StartupCoordinator {
+    pub fn new(workers: usize) -> StartupCoordinator {
+        let ready = WaitGroup::new();
+        ready.add(workers);
+
+        StartupCoordinator {
+            ready,
+            go: Event::new(),
+        }
+    }
+
+    //Called by each worker, returns once the coordinator has called go().
+    pub fn ready(&self) {
+        self.ready.done();
+        self.go.wait();
+    }
+
+    pub fn wait_until_ready(&self) {
+        self.ready.wait();
+    }
+
+    pub fn go(&self) {
+        self.go.set();
+    }
+}
+
+pub fn staged_startup() {
+    let coordinator = StartupCoordinator::new(4);
+    let started = AtomicUsize::new(0);
+
+    thread::scope(|s| {
+        for id in 0..4 {
+            let (coordinator, started) = (&coordinator, &started);
+            s.spawn(move || {
+                //Pretend setup takes a different amount of time on every worker.
+                thread::sleep(Duration::from_millis(5 * id));
+                coordinator.ready();
+                started.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        coordinator.wait_until_ready();
+        thread::sleep(Duration::from_millis(20));
+        println!(
+            "all 4 workers ready, started before go(): {}",
+            started.load(Ordering::SeqCst)
+        );
+        coordinator.go();
+    });
+
+    println!("started after go(): {}", started.load(Ordering::SeqCst));
+}
+
+pub fn waiting_on_a_wait_group_with_a_timeout() {
+    let group = WaitGroup::new();
+    group.add(2);
+
+    thread::scope(|s| {
+        for millis in [5, 10] {
+            let group = &group;
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(millis));
+                group.done();
+            });
+        }
+        println!("wait group finished in time: {}", group.wait_timeout(Duration::from_secs(1)));
+    });
+
+    //One worker never calls done(), so the count is stuck at 1.
+    group.add(2);
+    thread::scope(|s| {
+        s.spawn(|| group.done());
+        println!(
+            "wait group with a stuck worker finished in time: {}",
+            group.wait_timeout(Duration::from_millis(50))
+        );
+    });
+}
+
+//Java's CountDownLatch. Unlike a WaitGroup the count is fixed up front and can only go down, so
+// once it reaches zero the latch is open for good and every await_zero() after that returns
+// straight away. It's Clone, so each thread can just be given its own copy.
+#[derive(Clone)]
+pub struct CountdownLatch {
+    inner: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl CountdownLatch {
+    pub fn new(count: usize) -> CountdownLatch {
+        CountdownLatch {
+            inner: Arc::new((Mutex::new(count), Condvar::new())),
+        }
+    }
+
+    //Counting down an open latch does nothing, there is no way to wrap around and close it again.
+    pub fn count_down(&self) {
+        let (count, zero) = &*self.inner;
+        let mut count = count.lock().unwrap();
+        if *count > 0 {
+            *count -= 1;
+            if *count == 0 {
+                zero.notify_all();
+            }
+        }
+    }
+
+    pub fn await_zero(&self) {
+        let (count, zero) = &*self.inner;
+        let count = count.lock().unwrap();
+        let _count = zero.wait_while(count, |count| *count > 0).unwrap();
+    }
+}
+
+pub fn counting_down_a_latch() {
+    let latch = CountdownLatch::new(3);
+    let counted = Arc::new(AtomicUsize::new(0));
+
+    let workers: Vec<_> = (1..=3)
+        .map(|i| {
+            let (latch, counted) = (latch.clone(), Arc::clone(&counted));
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10 * i));
+                counted.fetch_add(1, Ordering::SeqCst);
+                latch.count_down();
+            })
+        })
+        .collect();
+
+    latch.await_zero();
+    println!(
+        "latch opened after {} of 3 workers counted down",
+        counted.load(Ordering::SeqCst)
+    );
+
+    for worker in workers {
+        worker.join().expect("Latch worker crashed");
+    }
+    latch.await_zero();
+    println!("waiting on an open latch returns right away");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gate_never_runs_more_than_its_limit() {
+        let gate = ConcurrencyGate::new(2);
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+
+        let results: Vec<usize> = thread::scope(|s| {
+            let handles: Vec<_> = (0..6)
+                .map(|i| {
+                    let (gate, running, peak) = (&gate, &running, &peak);
+                    s.spawn(move || {
+                        gate.run(|| {
+                            let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                            peak.fetch_max(now_running, Ordering::SeqCst);
+                            thread::sleep(Duration::from_millis(10));
+                            running.fetch_sub(1, Ordering::SeqCst);
+                            i
+                        })
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        assert_eq!(results, vec![0, 1, 2, 3, 4, 5]);
+        let peak = peak.into_inner();
+        assert!((1..=2).contains(&peak), "peak was {peak}");
+    }
+
+    #[test]
+    fn fair_semaphore_grants_permits_in_arrival_order() {
+        let semaphore = FairSemaphore::new(1);
+        let acquired_order = Mutex::new(Vec::new());
+
+        let held = semaphore.acquire();
+        thread::scope(|s| {
+            for id in 0..8 {
+                let (semaphore, acquired_order) = (&semaphore, &acquired_order);
+                s.spawn(move || {
+                    let _permit = semaphore.acquire();
+                    acquired_order.lock().unwrap().push(id);
+                });
+
+                //Wait for each thread to take its ticket before starting the next one.
+                while semaphore.queued() < id + 1 {
+                    thread::yield_now();
+                }
+            }
+            drop(held);
+        });
+
+        assert_eq!(acquired_order.into_inner().unwrap(), (0..8).collect::<Vec<_>>());
+        assert_eq!(semaphore.queued(), 0);
+    }
+
+    #[test]
+    fn timeout_barrier_has_one_leader_when_everyone_arrives() {
+        let barrier = TimeoutBarrier::new(3);
+
+        let leaders: Vec<bool> = thread::scope(|s| {
+            let handles: Vec<_> = (0..3)
+                .map(|_| s.spawn(|| barrier.wait(Duration::from_secs(5)).unwrap().is_leader()))
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        assert_eq!(leaders.iter().filter(|leader| **leader).count(), 1);
+    }
+
+    #[test]
+    fn a_timed_out_wait_breaks_the_barrier_for_everyone() {
+        let barrier = TimeoutBarrier::new(3);
+
+        //The second waiter would be happy to wait a minute, it fails once the first times out.
+        let started = Instant::now();
+        let results: Vec<bool> = thread::scope(|s| {
+            let handles: Vec<_> = [50, 60_000]
+                .into_iter()
+                .map(|millis| {
+                    let barrier = &barrier;
+                    s.spawn(move || barrier.wait(Duration::from_millis(millis)).is_err())
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        assert_eq!(results, vec![true, true]);
+        assert!(started.elapsed() < Duration::from_secs(30));
+        //Broken for good, even a late arrival fails straight away.
+        assert!(barrier.wait(Duration::from_secs(60)).is_err());
+    }
+
+    #[test]
+    fn workers_start_only_after_everyone_is_ready_and_go_is_called() {
+        let coordinator = StartupCoordinator::new(4);
+        let log = Mutex::new(Vec::new());
+
+        thread::scope(|s| {
+            for id in 0..4 {
+                let (coordinator, log) = (&coordinator, &log);
+                s.spawn(move || {
+                    thread::sleep(Duration::from_millis(5 * id));
+                    log.lock().unwrap().push(format!("ready {id}"));
+                    coordinator.ready();
+                    log.lock().unwrap().push(format!("start {id}"));
+                });
+            }
+
+            coordinator.wait_until_ready();
+            thread::sleep(Duration::from_millis(20));
+            log.lock().unwrap().push(String::from("go"));
+            coordinator.go();
+        });
+
+        let log = log.into_inner().unwrap();
+        assert_eq!(log.len(), 9);
+        let go = log.iter().position(|event| event == "go").unwrap();
+        assert_eq!(go, 4, "{log:?}");
+        assert!(log[..go].iter().all(|event| event.starts_with("ready")));
+        assert!(log[go + 1..].iter().all(|event| event.starts_with("start")));
+    }
+
+    #[test]
+    fn wait_timeout_succeeds_once_everyone_is_done() {
+        let group = WaitGroup::new();
+        assert!(group.wait_timeout(Duration::ZERO), "An empty group is already done");
+
+        group.add(3);
+        thread::scope(|s| {
+            for millis in [5, 10, 15] {
+                let group = &group;
+                s.spawn(move || {
+                    thread::sleep(Duration::from_millis(millis));
+                    group.done();
+                });
+            }
+            assert!(group.wait_timeout(Duration::from_secs(10)));
+        });
+    }
+
+    #[test]
+    fn wait_timeout_gives_up_on_a_stuck_worker() {
+        let group = WaitGroup::new();
+        group.add(2);
+        group.done();
+
+        let started = Instant::now();
+        assert!(!group.wait_timeout(Duration::from_millis(50)));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        //The count is still 1, so the last done() is what lets it through.
+        group.done();
+        assert!(group.wait_timeout(Duration::ZERO));
+    }
+
+    #[test]
+    fn waiters_are_released_only_at_zero() {
+        let latch = CountdownLatch::new(3);
+        let released = Arc::new(AtomicUsize::new(0));
+        let waiters: Vec<_> = (0..3)
+            .map(|_| {
+                let (latch, released) = (latch.clone(), Arc::clone(&released));
+                thread::spawn(move || {
+                    latch.await_zero();
+                    released.fetch_add(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+
+        latch.count_down();
+        latch.count_down();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(released.load(Ordering::SeqCst), 0, "A waiter got through before zero");
+
+        latch.count_down();
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        assert_eq!(released.load(Ordering::SeqCst), 3);
+
+        //Once open it stays open, even after more count_down()s.
+        latch.count_down();
+        latch.await_zero();
+    }
+}
@@ -0,0 +1,538 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//A job is just a boxed closure. It needs to be Send so that it can be moved onto one of the
+// worker threads and 'static because the pool has no idea how long the job will live for.
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+//This is the same basic layout as the thread pool from the final chapter of the Rust book. The
+// workers all share a single receiver behind an Arc<Mutex<T>> so that whichever worker is free
+// first is the one that grabs the next job.
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    sender: Option<JobSender>,
+    receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+    state: Arc<PoolState>,
+    //The size asked for, live_workers() can lag behind it for a while after a resize().
+    size: usize,
+    next_worker_id: usize,
+}
+
+//Like in the book, workers get either a job or an order to quit. Terminate is only sent by
+// resize(), it goes in the same queue as the jobs so anything queued before it still gets done.
+enum Message {
+    NewJob(Job),
+    Terminate,
+}
+
+//A pool made with ThreadPool::new() has an unbounded queue, so execute() never blocks. One made
+// with ThreadPool::bounded() uses a sync_channel, so execute() blocks while the queue is full.
+enum JobSender {
+    Unbounded(mpsc::Sender<Message>),
+    Bounded(mpsc::SyncSender<Message>),
+}
+
+#[derive(Debug)]
+pub struct PoolFull;
+
+//State shared between the pool and all of its workers.
+struct PoolState {
+    //Set when the pool is dropped so that workers quit without running whatever is still queued.
+    stopping: AtomicBool,
+    completed: AtomicUsize,
+    live: AtomicUsize,
+}
+
+impl ThreadPool {
+    pub fn new(size: usize) -> ThreadPool {
+        let (sender, receiver) = mpsc::channel();
+        ThreadPool::with_channel(size, JobSender::Unbounded(sender), receiver)
+    }
+
+    //At most `capacity` jobs can be waiting in the queue, on top of the ones already running.
+    pub fn bounded(size: usize, capacity: usize) -> ThreadPool {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        ThreadPool::with_channel(size, JobSender::Bounded(sender), receiver)
+    }
+
+    fn with_channel(
+        size: usize,
+        sender: JobSender,
+        receiver: mpsc::Receiver<Message>,
+    ) -> ThreadPool {
+        assert!(size > 0, "A thread pool needs at least one worker");
+
+        let mut pool = ThreadPool {
+            workers: Vec::with_capacity(size),
+            sender: Some(sender),
+            receiver: Arc::new(Mutex::new(receiver)),
+            state: Arc::new(PoolState {
+                stopping: AtomicBool::new(false),
+                completed: AtomicUsize::new(0),
+                live: AtomicUsize::new(0),
+            }),
+            size,
+            next_worker_id: 0,
+        };
+        pool.spawn_workers(size);
+
+        pool
+    }
+
+    fn spawn_workers(&mut self, count: usize) {
+        for _ in 0..count {
+            let worker = Worker::new(
+                self.next_worker_id,
+                Arc::clone(&self.receiver),
+                Arc::clone(&self.state),
+            );
+            self.workers.push(worker);
+            self.next_worker_id += 1;
+        }
+    }
+
+    fn send(&self, message: Message) {
+        let sent = match self.job_sender() {
+            JobSender::Unbounded(sender) => sender.send(message),
+            JobSender::Bounded(sender) => sender.send(message),
+        };
+        if sent.is_err() {
+            panic!("All workers have shut down");
+        }
+    }
+
+    //Fire and forget, there is no way to get anything back out of the job.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.send(Message::NewJob(Box::new(f)));
+    }
+
+    //Like execute(), but gives up if the job still can't be queued after `timeout`. Only a
+    // bounded pool can ever be full. SyncSender doesn't have a send_timeout() on stable Rust, so
+    // this keeps retrying try_send() until it works or time runs out. When try_send() fails the
+    // job is handed back in the error, which is what makes retrying possible.
+    pub fn execute_timeout<F>(&self, f: F, timeout: Duration) -> Result<(), PoolFull>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let deadline = Instant::now() + timeout;
+        let mut message = Message::NewJob(Box::new(f));
+
+        let sender = match self.job_sender() {
+            JobSender::Unbounded(_) => {
+                self.send(message);
+                return Ok(());
+            }
+            JobSender::Bounded(sender) => sender,
+        };
+
+        loop {
+            match sender.try_send(message) {
+                Ok(()) => return Ok(()),
+                Err(mpsc::TrySendError::Full(returned)) => message = returned,
+                Err(mpsc::TrySendError::Disconnected(_)) => panic!("All workers have shut down"),
+            }
+
+            if Instant::now() >= deadline {
+                return Err(PoolFull);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn job_sender(&self) -> &JobSender {
+        self.sender
+            .as_ref()
+            .expect("Sender only goes away when the pool is dropped")
+    }
+
+    //This is execute() with a way to get the result back. Each job gets its own oneshot channel
+    // (really just an mpsc channel that only ever has one value sent on it) and the worker
+    // fulfills it when the job finishes.
+    pub fn submit<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (result_tx, result_rx) = mpsc::channel();
+
+        self.execute(move || {
+            //If the JobHandle was dropped nobody cares about the result, so the error can be
+            // ignored.
+            let _ = result_tx.send(f());
+        });
+
+        JobHandle { receiver: result_rx }
+    }
+
+    //Taking self by value is what stops new jobs from being accepted, there is simply no pool
+    // left to call execute() on. Dropping the sender closes the channel, but everything already
+    // in the channel is still handed out before recv() starts returning errors, so the workers
+    // finish the queue and then exit. Returns how many jobs completed during the drain.
+    pub fn shutdown_graceful(mut self) -> usize {
+        let completed_before = self.state.completed.load(Ordering::SeqCst);
+
+        drop(self.sender.take());
+        self.join_workers();
+
+        self.state.completed.load(Ordering::SeqCst) - completed_before
+    }
+
+    //Growing just means spawning more workers on the same shared receiver. Shrinking can't pick
+    // which workers go, so it queues one Terminate per surplus worker and whichever workers pull
+    // them out of the queue exit, after finishing whatever job they were on. Jobs queued before
+    // the resize still run first and jobs queued after it are picked up by the workers that stay,
+    // so no work is lost. live_workers() only drops once the Terminates have been picked up.
+    pub fn resize(&mut self, new_size: usize) {
+        assert!(new_size > 0, "A thread pool needs at least one worker");
+
+        //Forget about workers that have already exited so the list doesn't keep growing.
+        self.workers.retain(|worker| {
+            worker.thread.as_ref().is_some_and(|thread| !thread.is_finished())
+        });
+
+        if new_size > self.size {
+            self.spawn_workers(new_size - self.size);
+        } else {
+            for _ in new_size..self.size {
+                self.send(Message::Terminate);
+            }
+        }
+        self.size = new_size;
+    }
+
+    pub fn live_workers(&self) -> usize {
+        self.state.live.load(Ordering::SeqCst)
+    }
+
+    fn join_workers(&mut self) {
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                thread.join().expect("Worker thread crashed");
+            }
+        }
+    }
+}
+
+//Dropping the pool is the abrupt version of shutdown. Jobs that are currently running get to
+// finish, but anything still sitting in the queue is thrown away.
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.state.stopping.store(true, Ordering::SeqCst);
+        drop(self.sender.take());
+        self.join_workers();
+    }
+}
+
+struct Worker {
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+        state: Arc<PoolState>,
+    ) -> Worker {
+        //Counted before the thread starts so live_workers() is right as soon as resize() returns.
+        state.live.fetch_add(1, Ordering::SeqCst);
+
+        let thread = thread::Builder::new()
+            .name(format!("pool-worker-{id}"))
+            .spawn(move || {
+                loop {
+                    //The lock is only held for the recv() call. The guard is a temporary that
+                    // gets dropped at the end of the statement, so the job runs without the lock.
+                    let message = receiver.lock().unwrap().recv();
+
+                    match message {
+                        Ok(_) if state.stopping.load(Ordering::SeqCst) => break,
+                        Ok(Message::NewJob(job)) => {
+                            job();
+                            state.completed.fetch_add(1, Ordering::SeqCst);
+                        }
+                        Ok(Message::Terminate) | Err(_) => break,
+                    }
+                }
+                state.live.fetch_sub(1, Ordering::SeqCst);
+            })
+            .expect("Failed to spawn worker thread");
+
+        Worker {
+            thread: Some(thread),
+        }
+    }
+}
+
+//Handle to the result of a single submitted job. It is sort of like a Future in other languages
+// except that there is no runtime, wait() just blocks the current thread.
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<T>,
+}
+
+impl<T> JobHandle<T> {
+    //Blocks until the job has finished. If the job panicked the worker never sends anything and
+    // the sender gets dropped, so that shows up as a recv() error.
+    pub fn wait(self) -> T {
+        self.receiver.recv().expect("Job panicked before producing a result")
+    }
+
+    //Non-blocking version. Once this returns Some the result has been taken out of the handle, so
+    // calling it again will return None.
+    pub fn try_get(&self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+pub fn submitting_jobs_with_handles() {
+    let pool = ThreadPool::new(3);
+
+    pool.execute(|| {
+        println!("execute() job ran, but nothing comes back from it");
+    });
+
+    let slow = pool.submit(|| {
+        thread::sleep(Duration::from_millis(100));
+        "slow job"
+    });
+    println!("slow job polled right away: {:?}", slow.try_get());
+
+    //The later jobs sleep less, so they will usually finish first. Because each handle owns its
+    // own channel the results still line up with the job that produced them.
+    let handles: Vec<JobHandle<u64>> = (0..5u64)
+        .map(|i| {
+            pool.submit(move || {
+                thread::sleep(Duration::from_millis(50 - i * 10));
+                i * i
+            })
+        })
+        .collect();
+
+    let results: Vec<u64> = handles.into_iter().map(JobHandle::wait).collect();
+    println!("submitted job results: {:?}", results);
+    println!("slow job after waiting: {}", slow.wait());
+}
+
+pub fn draining_a_pool_before_shutdown() {
+    let graceful_ran = Arc::new(AtomicUsize::new(0));
+    let pool = ThreadPool::new(2);
+
+    for _ in 0..20 {
+        let graceful_ran = Arc::clone(&graceful_ran);
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(5));
+            graceful_ran.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+
+    let drained = pool.shutdown_graceful();
+    println!(
+        "graceful shutdown: {} of 20 jobs ran, {drained} of them during the drain",
+        graceful_ran.load(Ordering::SeqCst)
+    );
+
+    //Same thing but the pool is just dropped, so most of the queue never runs.
+    let abrupt_ran = Arc::new(AtomicUsize::new(0));
+    let pool = ThreadPool::new(2);
+
+    for _ in 0..20 {
+        let abrupt_ran = Arc::clone(&abrupt_ran);
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(5));
+            abrupt_ran.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+
+    drop(pool);
+    println!("abrupt drop: {} of 20 jobs ran", abrupt_ran.load(Ordering::SeqCst));
+}
+
+pub fn timing_out_on_a_full_pool() {
+    //One worker and room for one queued job.
+    let pool = ThreadPool::bounded(1, 1);
+
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    pool.execute(move || {
+        let _ = release_rx.recv();
+    });
+    pool.execute(|| {});
+
+    //The worker can't pick up the queued job, so there is no room for another one.
+    let saturated = pool.execute_timeout(|| {}, Duration::from_millis(10));
+    println!("execute_timeout on a saturated pool: {:?}", saturated);
+
+    //This time the worker gets freed up while execute_timeout() is still retrying.
+    let releaser = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        drop(release_tx);
+    });
+    let freed = pool.execute_timeout(|| {}, Duration::from_millis(500));
+    println!("execute_timeout once a worker frees up: {:?}", freed);
+
+    releaser.join().expect("Releaser crashed");
+}
+
+pub fn resizing_a_pool() {
+    let ran = Arc::new(AtomicUsize::new(0));
+    let mut pool = ThreadPool::new(2);
+
+    let submit_batch = |pool: &ThreadPool| {
+        for _ in 0..10 {
+            let ran = Arc::clone(&ran);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(2));
+                ran.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+    };
+
+    submit_batch(&pool);
+    pool.resize(4);
+    println!("live workers after growing to 4: {}", pool.live_workers());
+    submit_batch(&pool);
+
+    //The 3 Terminates queue up behind the jobs above, the batch after them runs on 1 worker.
+    pool.resize(1);
+    submit_batch(&pool);
+
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while pool.live_workers() > 1 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+    println!("live workers after shrinking to 1: {}", pool.live_workers());
+
+    pool.shutdown_graceful();
+    println!("jobs run across the resizes: {} of 30", ran.load(Ordering::SeqCst));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn submitted_jobs_return_their_own_results() {
+        let pool = ThreadPool::new(3);
+
+        //The later jobs finish first, wait() still gives each handle its own job's result.
+        let handles: Vec<JobHandle<u64>> = (0..5u64)
+            .map(|i| {
+                pool.submit(move || {
+                    thread::sleep(Duration::from_millis(50 - i * 10));
+                    i * i
+                })
+            })
+            .collect();
+
+        let results: Vec<u64> = handles.into_iter().map(JobHandle::wait).collect();
+        assert_eq!(results, vec![0, 1, 4, 9, 16]);
+    }
+
+    #[test]
+    fn try_get_polls_without_blocking() {
+        let pool = ThreadPool::new(1);
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        let handle = pool.submit(move || {
+            let _ = release_rx.recv();
+            7
+        });
+        assert_eq!(handle.try_get(), None);
+
+        drop(release_tx);
+        assert_eq!(handle.wait(), 7);
+    }
+
+    #[test]
+    fn graceful_shutdown_runs_every_queued_job() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(2);
+
+        for _ in 0..20 {
+            let ran = Arc::clone(&ran);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(1));
+                ran.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        let drained = pool.shutdown_graceful();
+        assert_eq!(ran.load(Ordering::SeqCst), 20);
+        assert!(drained <= 20);
+    }
+
+    #[test]
+    fn execute_timeout_fails_on_a_full_pool_and_succeeds_once_freed() {
+        //One worker and room for one queued job.
+        let pool = ThreadPool::bounded(1, 1);
+
+        let (started_tx, started_rx) = mpsc::channel::<()>();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        });
+        started_rx.recv().unwrap();
+        pool.execute(|| {});
+
+        assert!(pool.execute_timeout(|| {}, Duration::from_millis(10)).is_err());
+
+        drop(release_tx);
+        assert!(pool.execute_timeout(|| {}, Duration::from_secs(5)).is_ok());
+    }
+
+    fn wait_for_live_workers(pool: &ThreadPool, expected: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.live_workers() != expected {
+            assert!(Instant::now() < deadline, "Stuck at {} workers", pool.live_workers());
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn live_workers_follow_resize() {
+        let mut pool = ThreadPool::new(2);
+        assert_eq!(pool.live_workers(), 2);
+
+        //Growing counts the new workers straight away.
+        pool.resize(4);
+        assert_eq!(pool.live_workers(), 4);
+
+        //Every job waits on a gate that stays closed until after the shrink, so when resize(1)
+        // runs 4 jobs are stuck in a worker and the other 8 are still in the queue.
+        let gate = Arc::new(Mutex::new(()));
+        let closed = gate.lock().unwrap();
+        let handles: Vec<_> = (0..12)
+            .map(|i| {
+                let gate = Arc::clone(&gate);
+                pool.submit(move || {
+                    drop(gate.lock().unwrap());
+                    i * 2
+                })
+            })
+            .collect();
+
+        //Shrinking only shows up once the extra workers have picked up their Terminate, which
+        // sits behind the queued jobs.
+        pool.resize(1);
+        assert_eq!(pool.state.completed.load(Ordering::SeqCst), 0);
+        drop(closed);
+
+        let results: Vec<i32> = handles.into_iter().map(JobHandle::wait).collect();
+        assert_eq!(results, (0..12).map(|i| i * 2).collect::<Vec<_>>());
+        wait_for_live_workers(&pool, 1);
+
+        //The worker that is left still runs everything queued after the shrink.
+        let handles: Vec<_> = (0..5).map(|i| pool.submit(move || i + 1)).collect();
+        let results: Vec<i32> = handles.into_iter().map(JobHandle::wait).collect();
+        assert_eq!(results, vec![1, 2, 3, 4, 5]);
+        assert_eq!(pool.live_workers(), 1);
+
+        pool.resize(3);
+        assert_eq!(pool.live_workers(), 3);
+        drop(pool);
+    }
+}
@@ -0,0 +1,7 @@
+use std::thread;
+
+fn main() {
+    let greeting = String::from("hello");
+    let handle = thread::spawn(|| println!("{greeting}"));
+    handle.join().unwrap();
+}